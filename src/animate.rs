@@ -0,0 +1,213 @@
+// Animation of the heatmap as the reads accumulate during the run, with a frame per hour
+// or, for reads without a start time, per twentieth of the reads
+use std::fs::File;
+use std::io::BufWriter;
+
+use image::codecs::gif::{GifEncoder, Repeat};
+use image::{Delay, DynamicImage, Frame, RgbImage};
+use imageproc::drawing::{draw_text, text_size};
+use log::info;
+
+use crate::extract_data::{add_reads_to_histogram, reads_to_histogram, RunReads};
+use crate::histogram::{Histogram2D, HistogramKind};
+use crate::intensity::{SampleNorm, Scaling};
+use crate::plot::{color_to_rgb, render_heatmap};
+use crate::transform::Axes;
+use crate::{axis_ticks, BackGround, Color};
+
+// runs of more hours than this have a frame every few hours
+const MAX_FRAMES: usize = 100;
+// the frames of reads without a start time
+const ORDER_FRAMES: usize = 20;
+const FRAME_DELAY_MS: u32 = 200;
+// the last frame with all the reads stays longer
+const LAST_FRAME_DELAY_MS: u32 = 2000;
+
+/// Save the cumulative heatmaps of the datasets as an animated GIF, with the colors scaled
+/// to the heatmap of all the reads such that the intensity of a bin only increases
+/// The datasets are normalized by all their reads as well, which is not possible for
+/// --sample-norm downsample
+pub fn write_animation(
+    path: &str,
+    runs: &[&RunReads],
+    labels: &[String],
+    axes: Axes,
+    background: BackGround,
+    colors: Vec<Color>,
+    scaling: Scaling,
+) {
+    info!("Saving animation to {path}");
+    let complete = runs
+        .iter()
+        .map(|run| reads_to_histogram(&run.reads, axes))
+        .collect::<Vec<_>>();
+    if scaling.sample_norm == SampleNorm::Downsample {
+        panic!("\n\nERROR: --animate cannot be used with --sample-norm downsample!");
+    }
+    let factors = complete
+        .iter()
+        .map(|histogram| normalization_factor(histogram, scaling.sample_norm))
+        .collect::<Vec<_>>();
+    let limits = scaling.limits(&scaling.prepare(complete));
+    let scaling = Scaling {
+        vmin: Some(limits.0),
+        vmax: Some(limits.1),
+        sample_norm: SampleNorm::None,
+        ..scaling
+    };
+    let legend = labels
+        .iter()
+        .cloned()
+        .zip(colors.iter().map(|c| color_to_rgb(*c)))
+        .collect::<Vec<_>>();
+    let frames = frames(runs);
+    let file = File::create(path).unwrap_or_else(|e| panic!("Error creating {path}: {e}"));
+    let mut encoder = GifEncoder::new_with_speed(BufWriter::new(file), 10);
+    encoder
+        .set_repeat(Repeat::Infinite)
+        .unwrap_or_else(|e| panic!("Error writing {path}: {e}"));
+    // the reads of each frame are added to those of the frames before
+    let mut cumulative = runs
+        .iter()
+        .map(|_| Histogram2D::new(HistogramKind::LengthAccuracy, axes))
+        .collect::<Vec<_>>();
+    let mut added = vec![0; runs.len()];
+    for (index, (stamp, counts)) in frames.iter().enumerate() {
+        for (((run, histogram), added), count) in
+            runs.iter().zip(&mut cumulative).zip(&mut added).zip(counts)
+        {
+            add_reads_to_histogram(histogram, &run.reads[*added..*count]);
+            *added = *count;
+        }
+        let histograms = cumulative
+            .iter()
+            .zip(&factors)
+            .map(|(histogram, factor)| {
+                let mut histogram = histogram.clone();
+                histogram.scale_counts(*factor);
+                histogram
+            })
+            .collect();
+        let mut image = render_heatmap(histograms, background, colors.clone(), scaling);
+        if !legend.is_empty() {
+            image = axis_ticks::add_legend(image, &legend);
+        }
+        image = add_stamp(image, background, stamp);
+        let delay = if index + 1 == frames.len() {
+            LAST_FRAME_DELAY_MS
+        } else {
+            FRAME_DELAY_MS
+        };
+        let frame = Frame::from_parts(
+            DynamicImage::ImageRgb8(image).into_rgba8(),
+            0,
+            0,
+            Delay::from_numer_denom_ms(delay, 1),
+        );
+        encoder
+            .encode_frame(frame)
+            .unwrap_or_else(|e| panic!("Error writing {path}: {e}"));
+    }
+}
+
+// the factor by which the counts of a dataset are normalized, from all of its reads,
+// as the normalization of the reads up to a frame would change the scale between frames
+fn normalization_factor(histogram: &Histogram2D, sample_norm: SampleNorm) -> f64 {
+    match sample_norm {
+        SampleNorm::Fraction if !histogram.is_empty() => 1.0 / histogram.total() as f64,
+        SampleNorm::Max if !histogram.is_empty() => 1.0 / histogram.max(),
+        _ => 1.0,
+    }
+}
+
+// the time stamp of each frame with the number of reads of each run up to that frame,
+// by the hour if all the runs have start times, and otherwise by the order of the reads
+fn frames(runs: &[&RunReads]) -> Vec<(String, Vec<usize>)> {
+    let hours = runs
+        .iter()
+        .map(|run| run.hours.as_deref())
+        .collect::<Option<Vec<_>>>();
+    match hours {
+        Some(hours) => {
+            let last = hours
+                .iter()
+                .filter_map(|h| h.last())
+                .fold(0.0f32, |a, b| a.max(*b))
+                .floor() as usize
+                + 1;
+            let step = last.div_ceil(MAX_FRAMES);
+            (1..=last.div_ceil(step))
+                .map(|frame| {
+                    let hour = frame * step;
+                    let counts = hours
+                        .iter()
+                        .map(|h| h.partition_point(|time| *time < hour as f32))
+                        .collect();
+                    (format!("{hour}h"), counts)
+                })
+                .collect()
+        }
+        None => (1..=ORDER_FRAMES)
+            .map(|frame| {
+                let counts = runs
+                    .iter()
+                    .map(|run| run.reads.len() * frame / ORDER_FRAMES)
+                    .collect();
+                (format!("{}% of reads", frame * 100 / ORDER_FRAMES), counts)
+            })
+            .collect(),
+    }
+}
+
+// the time stamp in the bottom right corner, above the labels of the ticks
+fn add_stamp(image: RgbImage, background: BackGround, stamp: &str) -> RgbImage {
+    let font = axis_ticks::load_font();
+    let (width, _height) = text_size(24.0, &font, stamp);
+    let color = axis_ticks::tick_color(background);
+    draw_text(&image, color, 580 - width as i32, 520, 24.0, &font, stamp)
+}
+
+#[test]
+fn test_frames() {
+    let read = crate::ReadAccuracy {
+        length: 1000,
+        identity: 95.0,
+    };
+    let timed = RunReads {
+        reads: vec![read; 4],
+        hours: Some(vec![0.2, 0.5, 1.0, 2.5]),
+    };
+    assert_eq!(
+        frames(&[&timed]),
+        vec![
+            (String::from("1h"), vec![2]),
+            (String::from("2h"), vec![3]),
+            (String::from("3h"), vec![4]),
+        ]
+    );
+    // without start times, the frames are by the order of the reads
+    let ordered = RunReads {
+        reads: vec![read; 40],
+        hours: None,
+    };
+    let ordered_frames = frames(&[&timed, &ordered]);
+    assert_eq!(ordered_frames.len(), ORDER_FRAMES);
+    assert_eq!(ordered_frames[0], (String::from("5% of reads"), vec![0, 2]));
+    assert_eq!(ordered_frames[19].1, vec![4, 40]);
+}
+
+#[test]
+fn test_normalization_factor() {
+    let axes = Axes::new(crate::transform::AccuracyScale::Percent);
+    let mut histogram = Histogram2D::new(HistogramKind::LengthAccuracy, axes);
+    for x in [1, 1, 1, 2] {
+        histogram.add(x, 1);
+    }
+    assert_eq!(normalization_factor(&histogram, SampleNorm::Fraction), 0.25);
+    assert_eq!(normalization_factor(&histogram, SampleNorm::Max), 1.0 / 3.0);
+    assert_eq!(normalization_factor(&histogram, SampleNorm::None), 1.0);
+    // the last frame, with all the reads, is normalized as the heatmap
+    let mut frame = histogram.clone();
+    frame.scale_counts(normalization_factor(&histogram, SampleNorm::Fraction));
+    assert_eq!(frame, SampleNorm::Fraction.apply(vec![histogram])[0]);
+}
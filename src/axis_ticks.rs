@@ -1,30 +1,77 @@
 use image::{ImageBuffer, Rgb};
 use imageproc::{
-    drawing::{draw_filled_rect, draw_text},
+    drawing::{draw_filled_rect, draw_text, text_size},
     rect::Rect,
 };
 use ab_glyph::FontVec;
 
-use crate::transform::transform_length;
+use crate::transform::{AccuracyScale, Axes, PLOT_SIZE};
 
 
 pub fn add_ticks(
-    mut image: ImageBuffer<Rgb<u8>, Vec<u8>>,
-    transform_accuracy: fn(f32) -> usize,
-    phred: bool,
+    image: ImageBuffer<Rgb<u8>, Vec<u8>>,
+    axes: &Axes,
+    background: crate::BackGround,
+) -> ImageBuffer<Rgb<u8>, Vec<u8>> {
+    let color = tick_color(background);
+    let font = load_font();
+    let image = add_length_ticks(image, axes, color, &font);
+    add_accuracy_ticks(image, axes, color, &font)
+}
+
+/// Axis ticks for the calibration plot, which has the predicted accuracy on the x-axis
+/// and the observed accuracy on the y-axis
+pub fn add_calibration_ticks(
+    image: ImageBuffer<Rgb<u8>, Vec<u8>>,
+    axes: &Axes,
+    background: crate::BackGround,
+) -> ImageBuffer<Rgb<u8>, Vec<u8>> {
+    let color = tick_color(background);
+    let font = load_font();
+    let image = add_predicted_accuracy_ticks(image, axes, color, &font);
+    add_accuracy_ticks(image, axes, color, &font)
+}
+
+/// Axis ticks for the time plot, which has the time into the run on the x-axis
+pub fn add_time_ticks(
+    image: ImageBuffer<Rgb<u8>, Vec<u8>>,
+    axes: &Axes,
     background: crate::BackGround,
 ) -> ImageBuffer<Rgb<u8>, Vec<u8>> {
-    // determine the color of ticks and labels based on the background
-    let color = match background {
+    let color = tick_color(background);
+    let font = load_font();
+    let image = add_hour_ticks(image, axes, color, &font);
+    add_accuracy_ticks(image, axes, color, &font)
+}
+
+// determine the color of ticks and labels based on the background
+pub(crate) fn tick_color(background: crate::BackGround) -> Rgb<u8> {
+    match background {
         crate::BackGround::Black => Rgb([255, 255, 255]),
         crate::BackGround::White => Rgb([0, 0, 0]),
-    };
+    }
+}
+
+pub(crate) fn load_font() -> FontVec {
     let font_data: &[u8] = include_bytes!("../dev/TimesNewRoman/times new roman.ttf");
-    let font: FontVec = FontVec::try_from_vec(font_data.to_vec()).expect("Error parsing font file");
+    FontVec::try_from_vec(font_data.to_vec()).expect("Error parsing font file")
+}
 
+fn add_length_ticks(
+    mut image: ImageBuffer<Rgb<u8>, Vec<u8>>,
+    axes: &Axes,
+    color: Rgb<u8>,
+    font: &FontVec,
+) -> ImageBuffer<Rgb<u8>, Vec<u8>> {
+    // only ticks below the maximal length are shown
+    let within = |tick: &&usize| **tick < axes.max_length;
     // add major x-axis ticks at the top and bottom, and add axis labels at the bottom
-    for (index, tick) in [10, 100, 1000, 10000, 100000].iter().enumerate() {
-        let xcoord = transform_length(*tick) as i32;
+    for (index, tick) in [10, 100, 1000, 10000, 100000, 1000000, 10000000]
+        .iter()
+        .filter(within)
+        .enumerate()
+    {
+        let xcoord = axes.transform_length(*tick) as i32;
         // I was wondering much later why pow is used here instead of just tick
         let pow = 10i32.pow((index + 1).try_into().unwrap());
         // use an offset of the length of the string representation of tick
@@ -39,97 +86,202 @@ pub fn add_ticks(
             xcoord - 1 - (offset * 5),
             560,
             24.0,
-            &font,
+            font,
             &format!("{pow}"),
         )
     }
 
     // adding intermediate x-axis ticks at the top, of height 6
-    for tick in &[5, 50, 500, 5000, 50000, 500000] {
+    for tick in [5, 50, 500, 5000, 50000, 500000, 5000000].iter().filter(within) {
         image = draw_filled_rect(
             &image,
-            Rect::at(transform_length(*tick) as i32, 0).of_size(1, 6),
+            Rect::at(axes.transform_length(*tick) as i32, 0).of_size(1, 6),
             color,
         );
     }
 
     // adding minor x-axis ticks at the top, of height 2
     for tick in 1..10 {
-        for m in &[1, 10, 100, 1000, 10000, 100000] {
-            image = draw_filled_rect(
-                &image,
-                Rect::at(transform_length(tick * m) as i32, 0).of_size(1, 2),
-                color,
-            );
+        for m in &[1, 10, 100, 1000, 10000, 100000, 1000000] {
+            if tick * m < axes.max_length {
+                image = draw_filled_rect(
+                    &image,
+                    Rect::at(axes.transform_length(tick * m) as i32, 0).of_size(1, 2),
+                    color,
+                );
+            }
         }
     }
+    image
+}
 
-    if phred {
-        // add major y-axis ticks left and right, and axis labels on the left
-        for tick in &[10, 20, 30] {
-            let ycoord = 600 - (15.0 * (*tick as f32)) as i32;
-            image = draw_filled_rect(&image, Rect::at(588, ycoord).of_size(12, 1), color);
-            image = draw_filled_rect(&image, Rect::at(0, ycoord).of_size(12, 1), color);
-            image = draw_text(
-                &image,
-                color,
-                15,
-                ycoord - 10,
-                24.0,
-                &font,
-                &format!("Q{tick}"),
-            );
-        }
-
-        // add intermediate y-axis ticks
-        for tick in &[5.0, 15.0, 25.0, 35.0] {
+// ticks with a label at an interval of whole hours that gives at most 8 labels,
+// and minor ticks every hour if these are not too dense
+fn add_hour_ticks(
+    mut image: ImageBuffer<Rgb<u8>, Vec<u8>>,
+    axes: &Axes,
+    color: Rgb<u8>,
+    font: &FontVec,
+) -> ImageBuffer<Rgb<u8>, Vec<u8>> {
+    let interval = [1, 2, 4, 6, 12, 24, 48]
+        .into_iter()
+        .find(|hours| axes.max_time / *hours as f32 <= 8.0)
+        .unwrap_or(168);
+    let hours = (1..).take_while(|hour| (*hour as f32) < axes.max_time);
+    for hour in hours.clone().filter(|hour| hour % interval == 0) {
+        let xcoord = axes.transform_time(hour as f32) as i32;
+        let label = format!("{hour}h");
+        image = draw_filled_rect(&image, Rect::at(xcoord, 0).of_size(1, 12), color);
+        image = draw_filled_rect(&image, Rect::at(xcoord, 588).of_size(1, 12), color);
+        image = draw_text(
+            &image,
+            color,
+            xcoord - 6 * label.len() as i32,
+            560,
+            24.0,
+            font,
+            &label,
+        );
+    }
+    if axes.max_time <= 100.0 {
+        for hour in hours {
             image = draw_filled_rect(
                 &image,
-                Rect::at(594, (15.0 * (*tick as f32)) as i32).of_size(6, 1),
+                Rect::at(axes.transform_time(hour as f32) as i32, 0).of_size(1, 2),
                 color,
             );
         }
+    }
+    image
+}
 
-        // add minor y-axis ticks
-        for tick in 0..40 {
-            image = draw_filled_rect(
-                &image,
-                Rect::at(598, (15.0 * (tick as f32)) as i32).of_size(2, 1),
-                color,
-            );
-        }
-    } else { // if not phred
-        // add major y-axis ticks ticks left and right, and axis labels on the left
-        for tick in &[80.0, 90.0] {
-            let ycoord = transform_accuracy(*tick) as i32;
-            image = draw_filled_rect(&image, Rect::at(588, ycoord).of_size(12, 1), color);
-            image = draw_filled_rect(&image, Rect::at(0, ycoord).of_size(12, 1), color);
-            image = draw_text(
-                &image,
-                color,
-                15,
-                ycoord - 10,
-                24.0,
-                &font,
-                &format!("{tick}%"),
-            );
-        }
-        // add intermediate y-axis ticks
-        for tick in &[75.0, 85.0, 95.0] {
-            image = draw_filled_rect(
-                &image,
-                Rect::at(594, transform_accuracy(*tick) as i32).of_size(6, 1),
-                color,
-            );
-        }
-        // add minor y-axis ticks
-        for tick in 70..100 {
-            image = draw_filled_rect(
-                &image,
-                Rect::at(598, transform_accuracy(tick as f32) as i32).of_size(2, 1),
-                color,
-            );
-        }
+fn add_accuracy_ticks(
+    mut image: ImageBuffer<Rgb<u8>, Vec<u8>>,
+    axes: &Axes,
+    color: Rgb<u8>,
+    font: &FontVec,
+) -> ImageBuffer<Rgb<u8>, Vec<u8>> {
+    let (major, intermediate, minor) = accuracy_ticks(axes);
+    // add major y-axis ticks left and right, and axis labels on the left
+    for tick in major {
+        let ycoord = axes.accuracy_position(tick) as i32;
+        image = draw_filled_rect(&image, Rect::at(588, ycoord).of_size(12, 1), color);
+        image = draw_filled_rect(&image, Rect::at(0, ycoord).of_size(12, 1), color);
+        image = draw_text(
+            &image,
+            color,
+            15,
+            ycoord - 10,
+            24.0,
+            font,
+            &accuracy_label(axes, tick),
+        );
+    }
+    // add intermediate y-axis ticks
+    for tick in intermediate {
+        image = draw_filled_rect(
+            &image,
+            Rect::at(594, axes.accuracy_position(tick) as i32).of_size(6, 1),
+            color,
+        );
+    }
+    // add minor y-axis ticks
+    for tick in minor {
+        image = draw_filled_rect(
+            &image,
+            Rect::at(598, axes.accuracy_position(tick) as i32).of_size(2, 1),
+            color,
+        );
+    }
+    image
+}
+
+// ticks with a label every 10, intermediate ticks in between and minor ticks every 1
+// within the range of the accuracy axis, in percent or phred
+fn accuracy_ticks(axes: &Axes) -> (Vec<f32>, Vec<f32>, Vec<f32>) {
+    let (min, max) = (axes.min_accuracy, axes.max_accuracy);
+    let integers: Vec<f32> = (min.ceil() as i32..=max.floor() as i32)
+        .map(|tick| tick as f32)
+        .collect();
+    let within = |tick: &&f32| min < **tick && **tick < max;
+    let major = integers
+        .iter()
+        .filter(within)
+        .filter(|tick| **tick % 10.0 == 0.0)
+        .copied()
+        .collect();
+    let intermediate = integers
+        .iter()
+        .filter(within)
+        .filter(|tick| **tick % 10.0 == 5.0)
+        .copied()
+        .collect();
+    // the minor ticks include the lowest accuracy in percent, or the highest in phred
+    let minor = integers
+        .iter()
+        .filter(|tick| match axes.scale {
+            AccuracyScale::Percent => **tick < max,
+            AccuracyScale::Phred => **tick > min,
+        })
+        .copied()
+        .collect();
+    (major, intermediate, minor)
+}
+
+fn accuracy_label(axes: &Axes, tick: f32) -> String {
+    match axes.scale {
+        AccuracyScale::Percent => format!("{tick}%"),
+        AccuracyScale::Phred => format!("Q{tick}"),
+    }
+}
+
+// the predicted accuracy is on a mirrored accuracy axis, so ticks are placed
+// at the mirrored position of the accuracy ticks, with labels at the bottom
+fn add_predicted_accuracy_ticks(
+    mut image: ImageBuffer<Rgb<u8>, Vec<u8>>,
+    axes: &Axes,
+    color: Rgb<u8>,
+    font: &FontVec,
+) -> ImageBuffer<Rgb<u8>, Vec<u8>> {
+    let (major, _intermediate, minor) = accuracy_ticks(axes);
+    let xcoord = |tick: f32| (PLOT_SIZE as f32 - axes.accuracy_position(tick)) as i32;
+    for tick in major {
+        let label = accuracy_label(axes, tick);
+        image = draw_filled_rect(&image, Rect::at(xcoord(tick), 0).of_size(1, 12), color);
+        image = draw_filled_rect(&image, Rect::at(xcoord(tick), 588).of_size(1, 12), color);
+        image = draw_text(
+            &image,
+            color,
+            xcoord(tick) - 5 * label.len() as i32,
+            560,
+            24.0,
+            font,
+            &label,
+        );
+    }
+    for tick in minor {
+        image = draw_filled_rect(&image, Rect::at(xcoord(tick), 0).of_size(1, 2), color);
+    }
+    image
+}
+
+/// Add labels, each in its own color, stacked in the top right corner
+pub fn add_legend(
+    mut image: ImageBuffer<Rgb<u8>, Vec<u8>>,
+    labels: &[(String, Rgb<u8>)],
+) -> ImageBuffer<Rgb<u8>, Vec<u8>> {
+    let font = load_font();
+    for (index, (label, color)) in labels.iter().enumerate() {
+        let (width, _height) = text_size(24.0, &font, label);
+        image = draw_text(
+            &image,
+            *color,
+            580 - width as i32,
+            20 + 26 * index as i32,
+            24.0,
+            &font,
+            label,
+        );
     }
     image
 }
@@ -0,0 +1,369 @@
+// Settings from a configuration file and from presets, which are used for the options
+// that were not given on the command line, with the configuration file overriding the preset
+use crate::{AxesArgs, ExtractArgs, PlotArgs};
+use clap::parser::ValueSource;
+use clap::{ArgMatches, ValueEnum};
+use kyber::extract_data::ReadLength;
+use kyber::flowcell::ChannelValue;
+use kyber::intensity::{self, CountScale, SampleNorm};
+use kyber::{gate, guides, sampling, utils, BackGround, Color};
+use serde::Deserialize;
+
+/// Settings for a type of reads
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Preset {
+    /// PacBio HiFi reads, with the accuracy in phred scale from Q20 to Q50
+    Hifi,
+    /// ultra-long ONT reads, with read lengths up to 4 Mb
+    OntUl,
+}
+
+// The keys of a configuration file are the long names of the options, e.g.
+// max-length = 4000000
+// color = ["blue", "green"]
+#[derive(Debug, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    threads: Option<usize>,
+    ubam: Option<bool>,
+    length: Option<String>,
+    per_read: Option<bool>,
+    calibration: Option<bool>,
+    time: Option<bool>,
+    flowcell: Option<String>,
+    split_by: Option<String>,
+    fraction: Option<f64>,
+    seed: Option<u32>,
+    max_reads: Option<usize>,
+    color: Option<Vec<String>>,
+    background: Option<String>,
+    phred: Option<bool>,
+    max_length: Option<usize>,
+    min_accuracy: Option<f32>,
+    max_accuracy: Option<f32>,
+    max_time: Option<f32>,
+    scale: Option<String>,
+    pseudocount: Option<f64>,
+    normalize: Option<bool>,
+    vmin: Option<f64>,
+    vmax: Option<f64>,
+    vmax_quantile: Option<f64>,
+    sample_norm: Option<String>,
+    smooth: Option<f64>,
+    contours: Option<u16>,
+    grid: Option<bool>,
+    guides: Option<bool>,
+    hline: Option<Vec<String>>,
+    vline: Option<Vec<String>>,
+    gate: Option<String>,
+    stats: Option<bool>,
+}
+
+impl Preset {
+    fn config(self) -> Config {
+        match self {
+            Preset::Hifi => Config {
+                phred: Some(true),
+                min_accuracy: Some(20.0),
+                max_accuracy: Some(50.0),
+                ..Default::default()
+            },
+            Preset::OntUl => Config {
+                max_length: Some(4_000_000),
+                ..Default::default()
+            },
+        }
+    }
+}
+
+impl Config {
+    /// The settings of the configuration file, and of the preset for what is not in the file
+    pub fn load(path: Option<&str>, preset: Option<Preset>) -> Config {
+        let preset = preset.map(Preset::config).unwrap_or_default();
+        let Some(path) = path else {
+            return preset;
+        };
+        utils::is_file(path).unwrap_or_else(|_| panic!("Configuration file {path} is invalid",));
+        let text = std::fs::read_to_string(path)
+            .unwrap_or_else(|e| panic!("\n\nERROR: cannot read configuration file {path}: {e}"));
+        parse_config(&text)
+            .unwrap_or_else(|e| panic!("\n\nERROR: invalid configuration file {path}:\n{e}"))
+            .or(preset)
+    }
+
+    // the settings of this configuration, and of the other for what is not set
+    fn or(self, other: Config) -> Config {
+        Config {
+            threads: self.threads.or(other.threads),
+            ubam: self.ubam.or(other.ubam),
+            length: self.length.or(other.length),
+            per_read: self.per_read.or(other.per_read),
+            calibration: self.calibration.or(other.calibration),
+            time: self.time.or(other.time),
+            flowcell: self.flowcell.or(other.flowcell),
+            split_by: self.split_by.or(other.split_by),
+            fraction: self.fraction.or(other.fraction),
+            seed: self.seed.or(other.seed),
+            max_reads: self.max_reads.or(other.max_reads),
+            color: self.color.or(other.color),
+            background: self.background.or(other.background),
+            phred: self.phred.or(other.phred),
+            max_length: self.max_length.or(other.max_length),
+            min_accuracy: self.min_accuracy.or(other.min_accuracy),
+            max_accuracy: self.max_accuracy.or(other.max_accuracy),
+            max_time: self.max_time.or(other.max_time),
+            scale: self.scale.or(other.scale),
+            pseudocount: self.pseudocount.or(other.pseudocount),
+            normalize: self.normalize.or(other.normalize),
+            vmin: self.vmin.or(other.vmin),
+            vmax: self.vmax.or(other.vmax),
+            vmax_quantile: self.vmax_quantile.or(other.vmax_quantile),
+            sample_norm: self.sample_norm.or(other.sample_norm),
+            smooth: self.smooth.or(other.smooth),
+            contours: self.contours.or(other.contours),
+            grid: self.grid.or(other.grid),
+            guides: self.guides.or(other.guides),
+            hline: self.hline.or(other.hline),
+            vline: self.vline.or(other.vline),
+            gate: self.gate.or(other.gate),
+            stats: self.stats.or(other.stats),
+        }
+    }
+
+    pub fn apply_stats(&self, stats: &mut bool, matches: &ArgMatches) {
+        set(stats, self.stats, matches, "stats");
+    }
+}
+
+fn parse_config(text: &str) -> Result<Config, String> {
+    toml::from_str(text).map_err(|e| e.to_string())
+}
+
+// the value of the configuration is used unless the option was given on the command line
+fn set<T>(field: &mut T, value: Option<T>, matches: &ArgMatches, id: &str) {
+    if let Some(value) = value {
+        if matches.value_source(id) != Some(ValueSource::CommandLine) {
+            *field = value;
+        }
+    }
+}
+
+// the values in the configuration are checked as on the command line
+fn check<T>(value: Result<T, String>) -> T {
+    value.unwrap_or_else(|e| panic!("\n\nERROR: invalid configuration: {e}!"))
+}
+
+impl ExtractArgs {
+    pub fn apply_config(&mut self, config: &Config, matches: &ArgMatches) {
+        set(&mut self.threads, config.threads, matches, "threads");
+        set(&mut self.ubam, config.ubam, matches, "ubam");
+        let length = config
+            .length
+            .as_deref()
+            .map(|l| ReadLength::from_str(l, false));
+        set(&mut self.length, length.map(check), matches, "length");
+        set(&mut self.per_read, config.per_read, matches, "per_read");
+        set(
+            &mut self.calibration,
+            config.calibration,
+            matches,
+            "calibration",
+        );
+        set(&mut self.time, config.time, matches, "time");
+        let flowcell = config
+            .flowcell
+            .as_deref()
+            .map(|v| ChannelValue::from_str(v, false));
+        set(
+            &mut self.flowcell,
+            flowcell.map(|v| Some(check(v))),
+            matches,
+            "flowcell",
+        );
+        let split_by = config.split_by.as_deref().map(utils::parse_split_by);
+        set(
+            &mut self.split_by,
+            split_by.map(|s| Some(check(s))),
+            matches,
+            "split_by",
+        );
+        let fraction = config
+            .fraction
+            .map(|f| sampling::parse_fraction(&f.to_string()));
+        set(
+            &mut self.fraction,
+            fraction.map(|f| Some(check(f))),
+            matches,
+            "fraction",
+        );
+        set(&mut self.seed, config.seed, matches, "seed");
+        set(
+            &mut self.max_reads,
+            config.max_reads.map(Some),
+            matches,
+            "max_reads",
+        );
+        if self.calibration && (self.ubam || self.split_by.is_some()) {
+            panic!("\n\nERROR: --calibration cannot be used with --ubam or --split-by!");
+        }
+        if self.time && (self.calibration || self.split_by.is_some()) {
+            panic!("\n\nERROR: --time cannot be used with --calibration or --split-by!");
+        }
+        if self.flowcell.is_some() && (self.calibration || self.time || self.split_by.is_some()) {
+            panic!(
+                "\n\nERROR: --flowcell cannot be used with --calibration, --time or --split-by!"
+            );
+        }
+        if self.per_read && (self.ubam || self.calibration || self.time || self.flowcell.is_some())
+        {
+            panic!("\n\nERROR: --per-read cannot be used with --ubam, --calibration, --time or --flowcell!");
+        }
+        // the alignments of a read after the last read that is used would be missing
+        if self.per_read && self.max_reads.is_some() {
+            panic!(
+                "\n\nERROR: --per-read cannot be used with --max-reads, use --fraction instead!"
+            );
+        }
+    }
+}
+
+impl PlotArgs {
+    pub fn apply_config(&mut self, config: &Config, matches: &ArgMatches) {
+        let color = config.color.as_ref().map(|colors| {
+            colors
+                .iter()
+                .map(|c| check(Color::from_str(c, true)))
+                .collect()
+        });
+        set(&mut self.color, color.map(Some), matches, "color");
+        let background = config
+            .background
+            .as_deref()
+            .map(|b| BackGround::from_str(b, true));
+        set(
+            &mut self.background,
+            background.map(check),
+            matches,
+            "background",
+        );
+        self.axes.apply_config(config, matches);
+        let scale = config
+            .scale
+            .as_deref()
+            .map(|s| CountScale::from_str(s, false));
+        set(&mut self.scale, scale.map(check), matches, "scale");
+        let pseudocount = config
+            .pseudocount
+            .map(|p| intensity::parse_positive(&p.to_string()));
+        set(
+            &mut self.pseudocount,
+            pseudocount.map(check),
+            matches,
+            "pseudocount",
+        );
+        set(&mut self.normalize, config.normalize, matches, "normalize");
+        set(&mut self.vmin, config.vmin.map(Some), matches, "vmin");
+        // a quantile on the command line replaces the vmax of the configuration and vice versa
+        let quantile = config
+            .vmax_quantile
+            .map(|q| Some(check(intensity::parse_quantile(&q.to_string()))));
+        if matches.value_source("vmax_quantile") != Some(ValueSource::CommandLine) {
+            set(&mut self.vmax, config.vmax.map(Some), matches, "vmax");
+        }
+        if matches.value_source("vmax") != Some(ValueSource::CommandLine) {
+            set(&mut self.vmax_quantile, quantile, matches, "vmax_quantile");
+        }
+        let sample_norm = config
+            .sample_norm
+            .as_deref()
+            .map(|n| SampleNorm::from_str(n, false));
+        set(
+            &mut self.sample_norm,
+            sample_norm.map(check),
+            matches,
+            "sample_norm",
+        );
+        let smooth = config
+            .smooth
+            .map(|s| Some(check(intensity::parse_positive(&s.to_string()))));
+        set(&mut self.smooth, smooth, matches, "smooth");
+        set(
+            &mut self.contours,
+            config.contours.map(Some),
+            matches,
+            "contours",
+        );
+        set(&mut self.grid, config.grid, matches, "grid");
+        set(&mut self.guides, config.guides, matches, "guides");
+        let hline = config.hline.as_ref().map(|lines| {
+            lines
+                .iter()
+                .map(|l| check(guides::parse_hline(l)))
+                .collect()
+        });
+        set(&mut self.hline, hline, matches, "hline");
+        let vline = config.vline.as_ref().map(|lines| {
+            lines
+                .iter()
+                .map(|l| check(guides::parse_vline(l)))
+                .collect()
+        });
+        set(&mut self.vline, vline, matches, "vline");
+        let gate = config
+            .gate
+            .as_deref()
+            .map(|g| Some(check(gate::parse_gate(g))));
+        set(&mut self.gate, gate, matches, "gate");
+    }
+}
+
+impl AxesArgs {
+    pub fn apply_config(&mut self, config: &Config, matches: &ArgMatches) {
+        set(&mut self.phred, config.phred, matches, "phred");
+        set(
+            &mut self.max_length,
+            config.max_length.map(Some),
+            matches,
+            "max_length",
+        );
+        set(
+            &mut self.min_accuracy,
+            config.min_accuracy.map(Some),
+            matches,
+            "min_accuracy",
+        );
+        set(
+            &mut self.max_accuracy,
+            config.max_accuracy.map(Some),
+            matches,
+            "max_accuracy",
+        );
+        set(
+            &mut self.max_time,
+            config.max_time.map(Some),
+            matches,
+            "max_time",
+        );
+    }
+}
+
+#[test]
+fn test_parse_config() {
+    let config = parse_config("max-length = 4000000\ncolor = [\"blue\"]\nphred = true\n").unwrap();
+    assert_eq!(config.max_length, Some(4_000_000));
+    assert_eq!(config.color, Some(vec!["blue".to_string()]));
+    assert_eq!(config.phred, Some(true));
+    assert!(parse_config("max_length = 4000000").is_err());
+    assert!(parse_config("max-length = \"long\"").is_err());
+}
+
+#[test]
+fn test_config_overrides_preset() {
+    let config = parse_config("max-accuracy = 60\nthreads = 8")
+        .unwrap()
+        .or(Preset::Hifi.config());
+    assert_eq!(config.phred, Some(true));
+    assert_eq!(config.min_accuracy, Some(20.0));
+    assert_eq!(config.max_accuracy, Some(60.0));
+    assert_eq!(config.threads, Some(8));
+}
@@ -0,0 +1,99 @@
+// Iso-density lines of a histogram, traced with marching squares over the grid of bins
+use image::{Rgb, RgbImage};
+use imageproc::drawing::draw_line_segment_mut;
+
+use crate::histogram::Histogram2D;
+use crate::intensity::Scaling;
+use crate::transform::PLOT_SIZE;
+
+const BINS: usize = PLOT_SIZE + 1;
+
+/// Draw lines at a number of equally spaced levels between the lowest and the highest
+/// intensity of the histogram, with the counts transformed as for the heatmap
+pub fn draw_contours(
+    image: &mut RgbImage,
+    histogram: &Histogram2D,
+    levels: usize,
+    color: Rgb<u8>,
+    scaling: Scaling,
+) {
+    // each dataset has its own limits, such that the lines of datasets of a different size are comparable
+    let limits = scaling.limits(std::slice::from_ref(histogram));
+    let mut grid = vec![0.0; BINS * BINS];
+    for ((x, y), count) in histogram.iter() {
+        grid[y * BINS + x] = scaling.relative(count, limits);
+    }
+    for level in (1..=levels).map(|l| l as f64 / (levels + 1) as f64) {
+        for y in 0..PLOT_SIZE {
+            for x in 0..PLOT_SIZE {
+                for (start, end) in cell_segments(&grid, x, y, level) {
+                    draw_line_segment_mut(image, start, end, color);
+                }
+            }
+        }
+    }
+}
+
+// the line segments of a level within the square between the centers of four bins
+fn cell_segments(grid: &[f64], x: usize, y: usize, level: f64) -> Vec<((f32, f32), (f32, f32))> {
+    // the corners in clockwise order, starting at the top left
+    let corners = [(x, y), (x + 1, y), (x + 1, y + 1), (x, y + 1)];
+    let values = corners.map(|(cx, cy)| grid[cy * BINS + cx]);
+    if values.iter().all(|v| *v < level) || values.iter().all(|v| *v >= level) {
+        return vec![];
+    }
+    // the points on the sides of the square at which the level is crossed,
+    // interpolated linearly between the corners
+    let mut crossings = vec![];
+    for side in 0..4 {
+        let (a, b) = (side, (side + 1) % 4);
+        if (values[a] < level) != (values[b] < level) {
+            let t = (level - values[a]) / (values[b] - values[a]);
+            let (ax, ay) = corners[a];
+            let (bx, by) = corners[b];
+            crossings.push((
+                (ax as f64 + t * (bx as f64 - ax as f64)) as f32,
+                (ay as f64 + t * (by as f64 - ay as f64)) as f32,
+            ));
+        }
+    }
+    // a square is crossed on two sides, or on all four sides at a saddle
+    crossings.chunks(2).map(|pair| (pair[0], pair[1])).collect()
+}
+
+#[test]
+fn test_cell_segments() {
+    let mut grid = vec![0.0; BINS * BINS];
+    grid[0] = 1.0;
+    // the line crosses the top and the left side of the square halfway
+    assert_eq!(
+        cell_segments(&grid, 0, 0, 0.5),
+        vec![((0.5, 0.0), (0.0, 0.5))]
+    );
+    assert!(cell_segments(&grid, 1, 1, 0.5).is_empty());
+    // a saddle with two opposite corners above the level
+    grid[BINS + 1] = 1.0;
+    assert_eq!(cell_segments(&grid, 0, 0, 0.5).len(), 2);
+}
+
+#[test]
+fn test_draw_contours() {
+    let mut histogram = Histogram2D::new(
+        crate::histogram::HistogramKind::LengthAccuracy,
+        crate::transform::Axes::new(crate::transform::AccuracyScale::Percent),
+    );
+    for _ in 0..100 {
+        histogram.add(300, 300);
+    }
+    let scaling = Scaling {
+        smooth: Some(5.0),
+        ..Default::default()
+    };
+    let histogram = scaling.prepare(vec![histogram]).remove(0);
+    let mut image = RgbImage::new(601, 601);
+    draw_contours(&mut image, &histogram, 3, Rgb([255, 0, 0]), scaling);
+    // the lines are closed around the peak, which itself is not drawn
+    assert_eq!(image.get_pixel(300, 300), &Rgb([0, 0, 0]));
+    let row = (0..601).filter(|x| image.get_pixel(*x, 300) == &Rgb([255, 0, 0]));
+    assert_eq!(row.count(), 6);
+}
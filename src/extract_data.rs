@@ -1,19 +1,233 @@
-use std::collections::HashMap;
+use std::cmp::max;
+use std::collections::{btree_map, BTreeMap, HashMap};
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Mutex};
+use std::thread;
 
-use log::info;
+use clap::ValueEnum;
+use log::{info, warn};
 use rust_htslib::{
-    bam::{self, Read},
+    bam::{
+        self,
+        record::{Aux, Cigar},
+        Read,
+    },
     htslib,
 };
 
-use crate::{identity, transform};
+use crate::histogram::{Histogram2D, HistogramKind};
+use crate::identity::{self, AlignmentDifferences};
+use crate::sampling::Sampling;
+use crate::transform::Axes;
 
-pub fn bam_to_hashmap(
+/// Property of a read by which the reads of a single file are split into groups
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SplitBy {
+    /// the read group ID from the RG tag
+    ReadGroup,
+    /// the sample of the read group, from the SM field of the @RG header line
+    Sample,
+    /// the barcode from the BC tag
+    Barcode,
+    /// any other two-letter aux tag
+    Tag([u8; 2]),
+}
+
+/// The length of a read that is plotted
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum ReadLength {
+    /// the length of the stored sequence, without hard clipped bases
+    #[default]
+    Query,
+    /// the length of the read including hard clipped bases, as it was sequenced
+    Original,
+    /// the length of the aligned part of the read, without soft and hard clipped bases
+    Aligned,
+    /// the length of the reference covered by the alignment
+    ReferenceSpan,
+}
+
+impl ReadLength {
+    /// The length of a record, which is 0 e.g. for a record without a stored sequence,
+    /// or for the aligned length and reference span of an unaligned record
+    pub fn of(self, record: &bam::Record) -> usize {
+        if self == ReadLength::Query {
+            return record.seq_len();
+        }
+        // unaligned records have no CIGAR, and the length of their sequence is the original length
+        if self == ReadLength::Original && record.raw_cigar().is_empty() {
+            return record.seq_len();
+        }
+        record
+            .cigar()
+            .iter()
+            .map(|entry| match (self, entry) {
+                (_, Cigar::Match(len) | Cigar::Equal(len) | Cigar::Diff(len)) => *len,
+                (ReadLength::Original | ReadLength::Aligned, Cigar::Ins(len)) => *len,
+                (ReadLength::Original, Cigar::SoftClip(len) | Cigar::HardClip(len)) => *len,
+                (ReadLength::ReferenceSpan, Cigar::Del(len) | Cigar::RefSkip(len)) => *len,
+                _ => 0,
+            } as usize)
+            .sum()
+    }
+
+    /// The name of the length as on the command line
+    pub fn name(self) -> String {
+        self.to_possible_value()
+            .map_or_else(String::new, |value| value.get_name().to_string())
+    }
+}
+
+/// Length and identity of a single read, before transformation to the plot coordinates
+/// so that the same reads can be plotted with different transformations
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ReadAccuracy {
+    pub length: u32,
+    pub identity: f32,
+}
+
+/// Reads of the groups of a single file, with the group name
+pub type GroupedReads = Vec<(String, Vec<ReadAccuracy>)>;
+
+// reads lacking the tag used for splitting end up in this group
+const UNASSIGNED: &str = "unassigned";
+
+// records are read and decompressed in batches of this size, which are processed by worker threads
+const BATCH_SIZE: usize = 500;
+
+pub fn bam_to_reads(
+    bam_file: &str,
+    threads: usize,
+    ubam: bool,
+    length: ReadLength,
+    per_read: bool,
+    sampling: &Sampling,
+) -> Vec<ReadAccuracy> {
+    let (decompression_threads, workers) = split_threads(threads);
+    let mut bam = open_bam(bam_file, decompression_threads);
+    let reads = records_to_reads(
+        &mut bam,
+        bam_file,
+        ubam,
+        length,
+        per_read,
+        workers,
+        sampling,
+        |_| String::new(),
+    )
+    .pop_first()
+    .map(|(_, reads)| reads)
+    .unwrap_or_default();
+    info!("Collected {} reads", reads.len());
+    if reads.is_empty() {
+        panic!("No reads found in BAM file {}", bam_file);
+    }
+    reads
+}
+
+/// Collect the reads per group, e.g. per read group or barcode, from a single file
+/// The groups are sorted by name
+pub fn bam_to_grouped_reads(
     bam_file: &str,
     threads: usize,
-    transform_accuracy: fn(f32) -> usize,
     ubam: bool,
-) -> HashMap<(usize, usize), i32> {
+    length: ReadLength,
+    per_read: bool,
+    split_by: &SplitBy,
+    sampling: &Sampling,
+) -> GroupedReads {
+    let (decompression_threads, workers) = split_threads(threads);
+    let mut bam = open_bam(bam_file, decompression_threads);
+    let samples = read_group_samples(bam.header());
+    let groups = records_to_reads(
+        &mut bam,
+        bam_file,
+        ubam,
+        length,
+        per_read,
+        workers,
+        sampling,
+        |record| {
+            let group = match split_by {
+                SplitBy::ReadGroup => get_aux_as_string(record, b"RG"),
+                SplitBy::Sample => get_aux_as_string(record, b"RG")
+                    .and_then(|read_group| samples.get(&read_group).cloned()),
+                SplitBy::Barcode => get_aux_as_string(record, b"BC"),
+                SplitBy::Tag(tag) => get_aux_as_string(record, tag),
+            };
+            group.unwrap_or_else(|| UNASSIGNED.to_string())
+        },
+    );
+    info!("Collected reads of {} groups", groups.len());
+    if groups.is_empty() {
+        panic!("No reads found in BAM file {}", bam_file);
+    }
+    if let Some(unassigned) = groups.get(UNASSIGNED) {
+        warn!(
+            "{} reads in {bam_file} could not be assigned to a group, as these lack the tag or hold an array in it",
+            unassigned.len()
+        );
+    }
+    groups.into_iter().collect()
+}
+
+/// Transform the reads to the plot coordinates, and count the reads in each bin
+pub fn reads_to_histogram(reads: &[ReadAccuracy], axes: Axes) -> Histogram2D {
+    let mut hist = Histogram2D::new(HistogramKind::LengthAccuracy, axes);
+    add_reads_to_histogram(&mut hist, reads);
+    hist
+}
+
+/// Add the reads to the counts of a histogram of lengths and accuracies, on its axes
+pub fn add_reads_to_histogram(hist: &mut Histogram2D, reads: &[ReadAccuracy]) {
+    let axes = hist.axes();
+    for read in reads {
+        let length = axes.transform_length(read.length as usize);
+        let error = axes.transform_accuracy(read.identity);
+        hist.add(length, error);
+    }
+}
+
+/// Extract the data of multiple files concurrently, with the threads divided among the files
+/// The results are returned in the same order as the files
+pub fn process_files_concurrently<T, F>(files: &[String], threads: usize, extract: F) -> Vec<T>
+where
+    T: Send,
+    F: Fn(&str, usize) -> T + Sync,
+{
+    let threads_per_file = divide_threads(threads, files.len());
+    thread::scope(|scope| {
+        let handles = files
+            .iter()
+            .zip(threads_per_file)
+            .map(|(file, threads)| {
+                let extract = &extract;
+                scope.spawn(move || extract(file, threads))
+            })
+            .collect::<Vec<_>>();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap_or_else(|e| panic::resume_unwind(e)))
+            .collect()
+    })
+}
+
+// divide the threads as equally as possible over the files, with at least one per file
+fn divide_threads(threads: usize, files: usize) -> Vec<usize> {
+    (0..files)
+        .map(|i| max(1, threads / files + usize::from(i < threads % files)))
+        .collect()
+}
+
+// divide the threads between htslib decompression and the workers processing the records
+fn split_threads(threads: usize) -> (usize, usize) {
+    let workers = max(1, threads / 2);
+    (max(1, threads.saturating_sub(workers)), workers)
+}
+
+fn open_bam(bam_file: &str, threads: usize) -> bam::Reader {
     let mut bam = if bam_file == "-" {
         bam::Reader::from_stdin().expect("\n\nError reading alignments from stdin.\nDid you include the file header with samtools view -h?\n\n\n\n")
     } else {
@@ -22,46 +236,1065 @@ pub fn bam_to_hashmap(
     };
     bam.set_threads(threads)
         .expect("Failure setting decompression threads");
-    let histogram = if ubam {
-        // for ubam input, do not apply a filter
-        // and use the quality scores to calculate the expected error rate
-        bam
-            .rc_records()
-            .map(|r| r.expect("Failure parsing ubam file"))
-            .filter(|read| read.flags() & htslib::BAM_FSECONDARY as u16 == 0)
-            .fold(HashMap::new(), |mut hist, record| {
-                let length = transform::transform_length(record.seq_len());
-                let error = transform_accuracy(identity::ubam_accuracy(record));
-                let entry = hist.entry((length, error)).or_insert(0);
-                *entry += 1;
-                hist
-            })
-    } else {
     bam
-        .rc_records()
-        .map(|r| r.expect("Failure parsing Bam file"))
-        .filter(|read| read.flags() & (htslib::BAM_FUNMAP | htslib::BAM_FSECONDARY) as u16 == 0)
-        .fold(HashMap::new(), |mut hist, record| {
-            let length = transform::transform_length(record.seq_len());
-            let error = transform_accuracy(identity::gap_compressed_identity(record));
-            let entry = hist.entry((length, error)).or_insert(0);
-            *entry += 1;
-            hist
+}
+
+// collect the length and identity of all records in the group returned by group_of,
+// with the alignments of a split read combined into a single read with per_read
+#[allow(clippy::too_many_arguments)]
+fn records_to_reads<F>(
+    bam: &mut bam::Reader,
+    bam_file: &str,
+    ubam: bool,
+    length: ReadLength,
+    per_read: bool,
+    workers: usize,
+    sampling: &Sampling,
+    group_of: F,
+) -> BTreeMap<String, Vec<ReadAccuracy>>
+where
+    F: Fn(&bam::Record) -> String + Sync,
+{
+    type Groups = BTreeMap<String, Vec<ReadAccuracy>>;
+    let (mut groups, split, mut skipped) = fold_records_parallel(
+        bam,
+        workers,
+        record_filter(ubam),
+        sampling,
+        |(groups, split, skipped): &mut (Groups, SplitReads, Skipped), index, record| {
+            // the alignments of a split read are only combined once all records are read,
+            // with the group of the first alignment, as each worker gets records in file order
+            if per_read && is_split(record) {
+                split
+                    .entry(record.qname().to_vec())
+                    .or_insert_with(|| ReadPieces::new(group_of(record), index))
+                    .add(record, length);
+                return;
+            }
+            let reads = groups.entry(group_of(record)).or_default();
+            match record_to_read(record, ubam, length) {
+                Ok(read) => reads.push(read),
+                Err(skip) => skipped.add(skip),
+            }
+        },
+        |(mut groups, mut split, skipped), (other_groups, other_split, other_skipped)| {
+            for (group, reads) in other_groups {
+                groups.entry(group).or_default().extend(reads);
+            }
+            for (name, pieces) in other_split {
+                match split.entry(name) {
+                    btree_map::Entry::Occupied(mut entry) => entry.get_mut().merge(pieces),
+                    btree_map::Entry::Vacant(entry) => {
+                        entry.insert(pieces);
+                    }
+                }
+            }
+            (groups, split, skipped.merge(other_skipped))
+        },
+    );
+    if per_read {
+        let alignments: usize = split.values().map(|pieces| pieces.alignments).sum();
+        info!(
+            "Combined {alignments} alignments into {} split reads in {bam_file}",
+            split.len()
+        );
+        for pieces in split.into_values() {
+            let reads = groups.entry(pieces.group.clone()).or_default();
+            match pieces.read(length) {
+                Ok(read) => reads.push(read),
+                Err(skip) => skipped.add(skip),
+            }
+        }
+    }
+    skipped.warn(bam_file);
+    // groups in which no read was used are dropped
+    groups
+        .into_iter()
+        .filter(|(_, reads)| !reads.is_empty())
+        .collect()
+}
+
+// the alignments of split reads by read name, in a BTreeMap such that the order of the reads
+// does not depend on the worker threads
+type SplitReads = BTreeMap<Vec<u8>, ReadPieces>;
+
+// the alignments of a read that is split over a primary and supplementary alignments,
+// e.g. a chimeric read or a read spanning a structural variant
+#[derive(Clone, Debug, PartialEq)]
+struct ReadPieces {
+    // the group of the first alignment in the file, with the index of its record
+    group: String,
+    first: usize,
+    alignments: usize,
+    // the length of the complete read, from the alignment with the most clipped bases
+    original: usize,
+    // the sum of the lengths of the alignments, for the aligned length and reference span
+    summed: usize,
+    differences: AlignmentDifferences,
+}
+
+impl ReadPieces {
+    fn new(group: String, first: usize) -> ReadPieces {
+        ReadPieces {
+            group,
+            first,
+            alignments: 0,
+            original: 0,
+            summed: 0,
+            differences: AlignmentDifferences::default(),
+        }
+    }
+
+    fn add(&mut self, record: &bam::Record, length: ReadLength) {
+        self.alignments += 1;
+        self.original = max(self.original, ReadLength::Original.of(record));
+        self.summed += length.of(record);
+        self.differences.add(AlignmentDifferences::of(record));
+    }
+
+    // the group is that of the first alignment, whichever worker found it
+    fn merge(&mut self, other: ReadPieces) {
+        if other.first < self.first {
+            self.group = other.group;
+            self.first = other.first;
+        }
+        self.alignments += other.alignments;
+        self.original = max(self.original, other.original);
+        self.summed += other.summed;
+        self.differences.add(other.differences);
+    }
+
+    // the query and original length are both the length of the complete read,
+    // as the stored sequence of a supplementary alignment is often hard clipped
+    fn read(&self, length: ReadLength) -> Result<ReadAccuracy, Skip> {
+        let length = match length {
+            ReadLength::Query | ReadLength::Original => self.original,
+            ReadLength::Aligned | ReadLength::ReferenceSpan => self.summed,
+        };
+        if length == 0 || self.differences.columns == 0.0 {
+            return Err(Skip::Length);
+        }
+        Ok(ReadAccuracy {
+            length: length as u32,
+            identity: self.differences.identity(),
         })
+    }
+}
+
+/// Collect the length and identity of records from an iterator, e.g. of `bam::Reader::records()`,
+/// with the same filters as for files: secondary alignments are skipped,
+/// and for aligned reads (not ubam) also unmapped reads
+/// With ubam, the identity is estimated from the base qualities, and reads without qualities are skipped
+/// Reads of which the chosen length is 0, e.g. without a stored sequence, are skipped as well
+pub fn reads_from_records<I>(records: I, ubam: bool, length: ReadLength) -> Vec<ReadAccuracy>
+where
+    I: IntoIterator<Item = bam::Record>,
+{
+    let keep = record_filter(ubam);
+    records
+        .into_iter()
+        .filter(keep)
+        .filter_map(|record| record_to_read(&record, ubam, length).ok())
+        .collect()
+}
+
+// for ubam input, only secondary alignments are removed
+fn record_filter(ubam: bool) -> fn(&bam::Record) -> bool {
+    if ubam {
+        is_not_secondary
+    } else {
+        is_mapped_and_not_secondary
+    }
+}
+
+/// Description of the filters on the reads that are used, e.g. to save along with the histograms
+pub fn describe_filters(
+    ubam: bool,
+    length: ReadLength,
+    per_read: bool,
+    sampling: &Sampling,
+) -> Vec<String> {
+    let mut filters = vec![String::from(if ubam {
+        "not secondary, with base qualities"
+    } else {
+        "mapped and not secondary"
+    })];
+    if length != ReadLength::Query {
+        filters.push(format!("{} length", length.name()));
+    }
+    if per_read {
+        filters.push(String::from("supplementary alignments combined per read"));
+    }
+    if let Some(fraction) = sampling.fraction {
+        filters.push(format!("fraction {fraction} with seed {}", sampling.seed));
+    }
+    if let Some(max_reads) = sampling.max_reads {
+        filters.push(format!("at most {max_reads} records read per file"));
+    }
+    filters
+}
+
+// for ubam input use the quality scores to calculate the expected error rate,
+// such that reads without qualities are skipped, as are reads without length
+fn record_to_read(
+    record: &bam::Record,
+    ubam: bool,
+    length: ReadLength,
+) -> Result<ReadAccuracy, Skip> {
+    let length = length.of(record);
+    if length == 0 {
+        return Err(Skip::Length);
+    }
+    let identity = if ubam {
+        identity::ubam_accuracy(record).ok_or(Skip::Qualities)?
+    } else {
+        identity::gap_compressed_identity(record)
     };
-    info!("Constructed hashmap for histogram");
-    if histogram.is_empty() {
-        panic!("No reads found in BAM file {}", bam_file);
+    Ok(ReadAccuracy {
+        length: length as u32,
+        identity,
+    })
+}
+
+// the missing value for which a record is not used
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Skip {
+    Qualities,
+    Length,
+    Time,
+    Channel,
+}
+
+// the number of records that were not used, by reason
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+struct Skipped {
+    without_qualities: usize,
+    without_length: usize,
+    without_time: usize,
+    without_channel: usize,
+}
+
+impl Skipped {
+    fn add(&mut self, skip: Skip) {
+        match skip {
+            Skip::Qualities => self.without_qualities += 1,
+            Skip::Length => self.without_length += 1,
+            Skip::Time => self.without_time += 1,
+            Skip::Channel => self.without_channel += 1,
+        }
+    }
+
+    fn merge(self, other: Skipped) -> Skipped {
+        Skipped {
+            without_qualities: self.without_qualities + other.without_qualities,
+            without_length: self.without_length + other.without_length,
+            without_time: self.without_time + other.without_time,
+            without_channel: self.without_channel + other.without_channel,
+        }
+    }
+
+    fn warn(&self, bam_file: &str) {
+        if self.without_qualities > 0 {
+            warn!(
+                "Skipped {} reads without base qualities in {bam_file}",
+                self.without_qualities
+            );
+        }
+        if self.without_length > 0 {
+            warn!(
+                "Skipped {} reads of length 0 in {bam_file}, e.g. without a stored sequence",
+                self.without_length
+            );
+        }
+        if self.without_time > 0 {
+            warn!(
+                "Skipped {} reads without a valid start time (st tag) in {bam_file}",
+                self.without_time
+            );
+        }
+        if self.without_channel > 0 {
+            warn!(
+                "Skipped {} reads without a channel (ch tag) in {bam_file}",
+                self.without_channel
+            );
+        }
+    }
+}
+
+fn is_not_secondary(record: &bam::Record) -> bool {
+    record.flags() & htslib::BAM_FSECONDARY as u16 == 0
+}
+
+fn is_mapped_and_not_secondary(record: &bam::Record) -> bool {
+    record.flags() & (htslib::BAM_FUNMAP | htslib::BAM_FSECONDARY) as u16 == 0
+}
+
+// a supplementary alignment, or a primary alignment with supplementary alignments in its SA tag
+fn is_split(record: &bam::Record) -> bool {
+    record.flags() & htslib::BAM_FSUPPLEMENTARY as u16 != 0 || record.aux(b"SA").is_ok()
+}
+
+/// The records are read and filtered on the current thread, and sent in batches to worker threads.
+/// Each worker folds the records of its batches in its own accumulator, with the index of each
+/// record among the records that are used, and the accumulators of all workers are merged at the end
+fn fold_records_parallel<A, F, M>(
+    bam: &mut bam::Reader,
+    workers: usize,
+    keep: fn(&bam::Record) -> bool,
+    sampling: &Sampling,
+    fold: F,
+    merge: M,
+) -> A
+where
+    A: Default + Send,
+    F: Fn(&mut A, usize, &bam::Record) + Sync,
+    M: Fn(A, A) -> A,
+{
+    let (sender, receiver) = mpsc::sync_channel::<(usize, Vec<bam::Record>)>(2 * workers);
+    let receiver = Mutex::new(receiver);
+    let failed = AtomicBool::new(false);
+    thread::scope(|scope| {
+        let handles = (0..workers)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut accumulator = A::default();
+                    let mut failure = None;
+                    loop {
+                        // the lock is only held while waiting for the next batch
+                        let batch = receiver.lock().expect("Failure receiving records").recv();
+                        let Ok((first, batch)) = batch else { break };
+                        // after a panic the remaining batches are drained, so that reading never blocks
+                        if failure.is_some() {
+                            continue;
+                        }
+                        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                            for (index, record) in batch.iter().enumerate() {
+                                fold(&mut accumulator, first + index, record);
+                            }
+                        }));
+                        if let Err(e) = result {
+                            failed.store(true, Ordering::Relaxed);
+                            failure = Some(e);
+                        }
+                    }
+                    match failure {
+                        Some(e) => Err(e),
+                        None => Ok(accumulator),
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let mut record = bam::Record::new();
+        let mut batch = Vec::with_capacity(BATCH_SIZE);
+        let mut sampled = 0;
+        while let Some(result) = bam.read(&mut record) {
+            result.expect("Failure parsing Bam file");
+            if !keep(&record) || !sampling.selects(record.qname()) {
+                continue;
+            }
+            // the records are counted before the workers skip reads, e.g. without qualities
+            if sampling.max_reads.is_some_and(|max_reads| sampled >= max_reads) {
+                info!("Stopped reading after {sampled} reads");
+                break;
+            }
+            sampled += 1;
+            // the copy does not keep the (non thread-safe) reference to the header
+            batch.push(record.clone());
+            if batch.len() == BATCH_SIZE {
+                if failed.load(Ordering::Relaxed) {
+                    break;
+                }
+                let batch = mem::replace(&mut batch, Vec::with_capacity(BATCH_SIZE));
+                sender
+                    .send((sampled - BATCH_SIZE, batch))
+                    .expect("Failure sending records to worker threads");
+            }
+        }
+        if !batch.is_empty() {
+            sender
+                .send((sampled - batch.len(), batch))
+                .expect("Failure sending records to worker threads");
+        }
+        drop(sender);
+
+        let mut merged = A::default();
+        for handle in handles {
+            match handle.join().expect("Worker thread failed") {
+                Ok(accumulator) => merged = merge(merged, accumulator),
+                Err(e) => panic::resume_unwind(e),
+            }
+        }
+        merged
+    })
+}
+
+// map each read group ID to its sample, from the @RG lines of the header
+fn read_group_samples(header: &bam::HeaderView) -> HashMap<String, String> {
+    String::from_utf8_lossy(header.as_bytes())
+        .lines()
+        .filter(|line| line.starts_with("@RG"))
+        .filter_map(|line| {
+            let field = |name: &str| {
+                line.split('\t')
+                    .find_map(|f| f.strip_prefix(name))
+                    .map(str::to_string)
+            };
+            Some((field("ID:")?, field("SM:")?))
+        })
+        .collect()
+}
+
+// the value of a tag as a string, or None if the tag is absent or holds an array,
+// such that the read is counted with the reads without the tag
+fn get_aux_as_string(record: &bam::Record, tag: &[u8]) -> Option<String> {
+    match record.aux(tag).ok()? {
+        Aux::String(v) => Some(v.to_string()),
+        Aux::Char(v) => Some((v as char).to_string()),
+        Aux::I8(v) => Some(v.to_string()),
+        Aux::U8(v) => Some(v.to_string()),
+        Aux::I16(v) => Some(v.to_string()),
+        Aux::U16(v) => Some(v.to_string()),
+        Aux::I32(v) => Some(v.to_string()),
+        Aux::U32(v) => Some(v.to_string()),
+        Aux::Float(v) => Some(v.to_string()),
+        Aux::Double(v) => Some(v.to_string()),
+        Aux::HexByteArray(v) => Some(v.to_string()),
+        _ => None,
     }
-    histogram
 }
 
-pub fn log_transform_hashmap(
-    hashmap: HashMap<(usize, usize), i32>,
-) -> HashMap<(usize, usize), i32> {
-    let mut transformed_hashmap = HashMap::new();
-    for ((key, value), count) in hashmap {
-        transformed_hashmap.insert((key, value), (count as f32).log2() as i32);
+/// Predicted accuracy from the base qualities and observed accuracy from the alignment of a read
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CalibrationAccuracy {
+    pub predicted: f32,
+    pub observed: f32,
+}
+
+/// Collect the accuracy predicted by the base qualities and the accuracy
+/// observed from the alignment of aligned reads to check calibration of quality scores
+pub fn bam_to_calibration_reads(
+    bam_file: &str,
+    threads: usize,
+    sampling: &Sampling,
+) -> Vec<CalibrationAccuracy> {
+    let (decompression_threads, workers) = split_threads(threads);
+    let mut bam = open_bam(bam_file, decompression_threads);
+    let (reads, skipped) = fold_records_parallel(
+        &mut bam,
+        workers,
+        is_mapped_and_not_secondary,
+        sampling,
+        |(reads, skipped): &mut (Vec<CalibrationAccuracy>, Skipped), _index, record| {
+            // reads without base qualities cannot be compared
+            match identity::ubam_accuracy(record) {
+                Some(predicted) => reads.push(CalibrationAccuracy {
+                    predicted,
+                    observed: identity::gap_compressed_identity(record),
+                }),
+                None => skipped.add(Skip::Qualities),
+            }
+        },
+        |(mut reads, skipped), (other_reads, other_skipped)| {
+            reads.extend(other_reads);
+            (reads, skipped.merge(other_skipped))
+        },
+    );
+    skipped.warn(bam_file);
+    info!("Collected {} reads for calibration", reads.len());
+    if reads.is_empty() {
+        panic!("No aligned reads with base qualities found in BAM file {}", bam_file);
     }
-    transformed_hashmap
+    reads
+}
+
+/// Transform the predicted (x) and observed (y) accuracy to the plot coordinates,
+/// and count the reads in each bin
+pub fn calibration_to_histogram(reads: &[CalibrationAccuracy], axes: Axes) -> Histogram2D {
+    let mut hist = Histogram2D::new(HistogramKind::Calibration, axes);
+    for read in reads {
+        let predicted = axes.transform_predicted_accuracy(read.predicted);
+        let observed = axes.transform_accuracy(read.observed);
+        hist.add(predicted, observed);
+    }
+    hist
+}
+
+/// Time into the sequencing run in hours and identity of a single read, with its length
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TimeAccuracy {
+    pub time: f32,
+    pub identity: f32,
+    pub length: u32,
+}
+
+/// Collect the time into the run from the start time of the read (st tag) of ONT reads,
+/// relative to the start of the run in the DT field of their read group, or to the earliest
+/// read without it, to follow the accuracy during a run, e.g. through pore degradation
+pub fn bam_to_time_reads(
+    bam_file: &str,
+    threads: usize,
+    ubam: bool,
+    length: ReadLength,
+    sampling: &Sampling,
+) -> Vec<TimeAccuracy> {
+    let (decompression_threads, workers) = split_threads(threads);
+    let mut bam = open_bam(bam_file, decompression_threads);
+    let run_starts = read_group_run_starts(bam.header());
+    // the read with the start of its read and of its run in seconds since the epoch
+    type Timed = Vec<(ReadAccuracy, f64, Option<f64>)>;
+    let (timed, skipped) = fold_records_parallel(
+        &mut bam,
+        workers,
+        record_filter(ubam),
+        sampling,
+        |(timed, skipped): &mut (Timed, Skipped), _index, record| {
+            let Some(start) = get_aux_as_string(record, b"st").and_then(|st| parse_timestamp(&st))
+            else {
+                skipped.add(Skip::Time);
+                return;
+            };
+            let run_start = get_aux_as_string(record, b"RG")
+                .and_then(|read_group| run_starts.get(&read_group).copied());
+            match record_to_read(record, ubam, length) {
+                Ok(read) => timed.push((read, start, run_start)),
+                Err(skip) => skipped.add(skip),
+            }
+        },
+        |(mut timed, skipped), (other, other_skipped)| {
+            timed.extend(other);
+            (timed, skipped.merge(other_skipped))
+        },
+    );
+    skipped.warn(bam_file);
+    info!("Collected {} reads with a start time", timed.len());
+    if timed.is_empty() {
+        panic!("No reads with a start time (st tag) found in BAM file {bam_file}");
+    }
+    let starts = timed
+        .iter()
+        .map(|(_read, start, run_start)| (*start, *run_start))
+        .collect::<Vec<_>>();
+    timed
+        .iter()
+        .zip(hours_into_run(&starts, bam_file))
+        .map(|((read, _start, _run_start), time)| TimeAccuracy {
+            time,
+            identity: read.identity,
+            length: read.length,
+        })
+        .collect()
+}
+
+// the hours between the start of each read and the start of its run,
+// or the earliest read without a start of the run
+fn hours_into_run(starts: &[(f64, Option<f64>)], bam_file: &str) -> Vec<f32> {
+    let earliest = starts
+        .iter()
+        .filter(|(_start, run_start)| run_start.is_none())
+        .map(|(start, _run_start)| *start)
+        .fold(f64::INFINITY, f64::min);
+    if earliest.is_finite() {
+        warn!("Using the earliest read as the start of the run for reads without DT in their read group in {bam_file}");
+    }
+    starts
+        .iter()
+        .map(|(start, run_start)| {
+            ((start - run_start.unwrap_or(earliest)) / 3600.0).max(0.0) as f32
+        })
+        .collect()
+}
+
+/// Transform the time (x) and accuracy (y) to the plot coordinates, and count the reads in each bin
+pub fn time_to_histogram(reads: &[TimeAccuracy], axes: Axes) -> Histogram2D {
+    let mut hist = Histogram2D::new(HistogramKind::TimeAccuracy, axes);
+    for read in reads {
+        hist.add(
+            axes.transform_time(read.time),
+            axes.transform_accuracy(read.identity),
+        );
+    }
+    hist
+}
+
+/// The reads of a file in the order in which these were sequenced, with their time into the run
+/// in hours if the reads have a start time (st tag), and otherwise in the order of the file
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RunReads {
+    pub reads: Vec<ReadAccuracy>,
+    pub hours: Option<Vec<f32>>,
+}
+
+/// Collect the reads of a file in the order of the run, e.g. to animate the run
+pub fn bam_to_run_reads(
+    bam_file: &str,
+    threads: usize,
+    ubam: bool,
+    length: ReadLength,
+    sampling: &Sampling,
+) -> RunReads {
+    let (decompression_threads, workers) = split_threads(threads);
+    let mut bam = open_bam(bam_file, decompression_threads);
+    let run_starts = read_group_run_starts(bam.header());
+    // the index of the record with the read and the start of the read and of its run
+    type Ordered = Vec<(usize, ReadAccuracy, Option<f64>, Option<f64>)>;
+    let (mut ordered, skipped) = fold_records_parallel(
+        &mut bam,
+        workers,
+        record_filter(ubam),
+        sampling,
+        |(ordered, skipped): &mut (Ordered, Skipped), index, record| {
+            let read = match record_to_read(record, ubam, length) {
+                Ok(read) => read,
+                Err(skip) => return skipped.add(skip),
+            };
+            let start = get_aux_as_string(record, b"st").and_then(|st| parse_timestamp(&st));
+            let run_start = get_aux_as_string(record, b"RG")
+                .and_then(|read_group| run_starts.get(&read_group).copied());
+            ordered.push((index, read, start, run_start));
+        },
+        |(mut ordered, skipped), (other, other_skipped)| {
+            ordered.extend(other);
+            (ordered, skipped.merge(other_skipped))
+        },
+    );
+    skipped.warn(bam_file);
+    info!("Collected {} reads", ordered.len());
+    if ordered.is_empty() {
+        panic!("No reads found in BAM file {bam_file}");
+    }
+    ordered.sort_unstable_by_key(|(index, ..)| *index);
+    let without_time = ordered
+        .iter()
+        .filter(|(.., start, _)| start.is_none())
+        .count();
+    if without_time == ordered.len() {
+        warn!("No reads with a start time (st tag) in {bam_file}, using the order of the reads in the file");
+        return RunReads {
+            reads: ordered.into_iter().map(|(_, read, ..)| read).collect(),
+            hours: None,
+        };
+    }
+    if without_time > 0 {
+        warn!("Skipped {without_time} reads without a valid start time (st tag) in {bam_file}");
+    }
+    let starts = ordered
+        .iter()
+        .filter_map(|(_, _, start, run_start)| Some(((*start)?, *run_start)))
+        .collect::<Vec<_>>();
+    let mut timed = ordered
+        .into_iter()
+        .filter(|(.., start, _)| start.is_some())
+        .map(|(_, read, ..)| read)
+        .zip(hours_into_run(&starts, bam_file))
+        .collect::<Vec<_>>();
+    timed.sort_by(|(_, a), (_, b)| a.total_cmp(b));
+    let (reads, hours) = timed.into_iter().unzip();
+    RunReads {
+        reads,
+        hours: Some(hours),
+    }
+}
+
+/// Number of reads of a channel of the flow cell, with the sums of their identity and length
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ChannelAccuracy {
+    pub reads: usize,
+    pub identity: f64,
+    pub bases: u64,
+}
+
+impl ChannelAccuracy {
+    fn add(&mut self, read: ReadAccuracy) {
+        self.reads += 1;
+        self.identity += f64::from(read.identity);
+        self.bases += u64::from(read.length);
+    }
+
+    fn merge(&mut self, other: ChannelAccuracy) {
+        self.reads += other.reads;
+        self.identity += other.identity;
+        self.bases += other.bases;
+    }
+
+    /// The mean identity of the reads in percent
+    pub fn mean_identity(&self) -> f32 {
+        (self.identity / self.reads as f64) as f32
+    }
+}
+
+/// The reads of each channel of the flow cell, by channel number
+pub type Channels = BTreeMap<u32, ChannelAccuracy>;
+
+/// Collect the reads of each channel from the channel tag (ch) of ONT reads,
+/// to see whether the accuracy differs between parts of the flow cell
+pub fn bam_to_channels(
+    bam_file: &str,
+    threads: usize,
+    ubam: bool,
+    length: ReadLength,
+    sampling: &Sampling,
+) -> Channels {
+    let (decompression_threads, workers) = split_threads(threads);
+    let mut bam = open_bam(bam_file, decompression_threads);
+    let (channels, skipped) = fold_records_parallel(
+        &mut bam,
+        workers,
+        record_filter(ubam),
+        sampling,
+        |(channels, skipped): &mut (Channels, Skipped), _index, record| {
+            let Some(channel) = get_aux_as_string(record, b"ch").and_then(|ch| ch.parse().ok())
+            else {
+                skipped.add(Skip::Channel);
+                return;
+            };
+            match record_to_read(record, ubam, length) {
+                Ok(read) => channels.entry(channel).or_default().add(read),
+                Err(skip) => skipped.add(skip),
+            }
+        },
+        |(mut channels, skipped), (other, other_skipped)| {
+            for (channel, reads) in other {
+                channels.entry(channel).or_default().merge(reads);
+            }
+            (channels, skipped.merge(other_skipped))
+        },
+    );
+    skipped.warn(bam_file);
+    info!("Collected reads of {} channels", channels.len());
+    if channels.is_empty() {
+        panic!("No reads with a channel (ch tag) found in BAM file {bam_file}");
+    }
+    channels
+}
+
+// map each read group ID to the start of its run, from the DT field of the @RG lines of the header
+fn read_group_run_starts(header: &bam::HeaderView) -> HashMap<String, f64> {
+    String::from_utf8_lossy(header.as_bytes())
+        .lines()
+        .filter(|line| line.starts_with("@RG"))
+        .filter_map(|line| {
+            let field = |name: &str| line.split('\t').find_map(|f| f.strip_prefix(name));
+            Some((field("ID:")?.to_string(), parse_timestamp(field("DT:")?)?))
+        })
+        .collect()
+}
+
+// seconds since the epoch of an ISO 8601 date and time as in the st tag of ONT reads,
+// e.g. 2023-07-19T18:06:22.154+00:00, with the time zone as an offset, Z or absent for UTC
+fn parse_timestamp(timestamp: &str) -> Option<f64> {
+    let (date, time) = timestamp.split_once('T')?;
+    let mut date = date.splitn(3, '-').map(|d| d.parse::<i64>().ok());
+    let (year, month, day) = (date.next()??, date.next()??, date.next()??);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    let (time, offset) = match time.find(['+', '-', 'Z']) {
+        Some(position) => time.split_at(position),
+        None => (time, ""),
+    };
+    let mut time = time.splitn(3, ':').map(|t| t.parse::<f64>().ok());
+    let (hours, minutes, seconds) = (time.next()??, time.next()??, time.next()??);
+    let offset = match offset {
+        "" | "Z" => 0.0,
+        offset => {
+            let sign = if offset.starts_with('-') { -1.0 } else { 1.0 };
+            let digits = offset[1..].replace(':', "");
+            if digits.len() != 4 {
+                return None;
+            }
+            let (hours, minutes) = digits.split_at(2);
+            sign * (hours.parse::<f64>().ok()? * 3600.0 + minutes.parse::<f64>().ok()? * 60.0)
+        }
+    };
+    // the days since 1970-01-01 in the proleptic Gregorian calendar
+    // with years starting in March, such that the leap day is at the end of the year
+    let (year, month) = match month {
+        1 | 2 => (year - 1, month + 9),
+        _ => (year, month - 3),
+    };
+    let year_of_era = year.rem_euclid(400);
+    let day_of_era =
+        year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + (153 * month + 2) / 5 + day - 1;
+    let days = year.div_euclid(400) * 146097 + day_of_era - 719468;
+    Some(days as f64 * 86400.0 + hours * 3600.0 + minutes * 60.0 + seconds - offset)
+}
+
+#[test]
+fn test_get_aux_as_string() {
+    let mut record = bam::Record::new();
+    record.push_aux(b"RG", Aux::String("rg1")).unwrap();
+    record.push_aux(b"HP", Aux::U8(2)).unwrap();
+    let array: &[u8] = &[1, 2];
+    record.push_aux(b"XB", Aux::ArrayU8(array.into())).unwrap();
+    assert_eq!(get_aux_as_string(&record, b"RG"), Some(String::from("rg1")));
+    assert_eq!(get_aux_as_string(&record, b"HP"), Some(String::from("2")));
+    // an array is treated as a missing tag, rather than stopping the run
+    assert_eq!(get_aux_as_string(&record, b"XB"), None);
+    assert_eq!(get_aux_as_string(&record, b"BC"), None);
+}
+
+#[test]
+fn test_parallel_processing_is_deterministic() {
+    let single = bam_to_reads(
+        "test-data/small-test-phased.bam",
+        1,
+        false,
+        ReadLength::Query,
+        false,
+        &Sampling::default(),
+    );
+    let parallel = bam_to_reads(
+        "test-data/small-test-phased.bam",
+        8,
+        false,
+        ReadLength::Query,
+        false,
+        &Sampling::default(),
+    );
+    assert_eq!(single.len(), parallel.len());
+    assert_eq!(
+        reads_to_histogram(&single, Axes::new(crate::transform::AccuracyScale::Percent)),
+        reads_to_histogram(&parallel, Axes::new(crate::transform::AccuracyScale::Percent))
+    );
+}
+
+#[test]
+fn test_split_threads() {
+    assert_eq!(split_threads(1), (1, 1));
+    assert_eq!(split_threads(4), (2, 2));
+    assert_eq!(split_threads(7), (4, 3));
+}
+
+#[test]
+fn test_divide_threads() {
+    assert_eq!(divide_threads(8, 3), vec![3, 3, 2]);
+    assert_eq!(divide_threads(2, 3), vec![1, 1, 1]);
+    assert_eq!(divide_threads(4, 1), vec![4]);
+}
+
+#[test]
+fn test_process_files_concurrently() {
+    let files = vec![
+        String::from("test-data/small-test-phased.bam"),
+        String::from("test-data/small-test-phased.bam"),
+    ];
+    let reads = process_files_concurrently(&files, 4, |f, threads| {
+        bam_to_reads(
+            f,
+            threads,
+            false,
+            ReadLength::Query,
+            false,
+            &Sampling::default(),
+        )
+    });
+    assert_eq!(reads.len(), 2);
+    assert_eq!(reads[0].len(), reads[1].len());
+}
+
+#[test]
+fn test_subsampling() {
+    let all = bam_to_reads(
+        "test-data/small-test-phased.bam",
+        2,
+        false,
+        ReadLength::Query,
+        false,
+        &Sampling::default(),
+    );
+    let sampling = Sampling {
+        fraction: Some(0.5),
+        seed: 1,
+        max_reads: None,
+    };
+    let half = bam_to_reads(
+        "test-data/small-test-phased.bam",
+        2,
+        false,
+        ReadLength::Query,
+        false,
+        &sampling,
+    );
+    assert!(half.len() < all.len());
+    let again = bam_to_reads(
+        "test-data/small-test-phased.bam",
+        2,
+        false,
+        ReadLength::Query,
+        false,
+        &sampling,
+    );
+    assert_eq!(half.len(), again.len());
+    let sampling = Sampling {
+        max_reads: Some(100),
+        ..Default::default()
+    };
+    let first = bam_to_reads(
+        "test-data/small-test-phased.bam",
+        2,
+        false,
+        ReadLength::Query,
+        false,
+        &sampling,
+    );
+    assert_eq!(first.len(), 100);
+}
+
+#[test]
+fn test_reads_from_records() {
+    let mut bam = bam::Reader::from_path("test-data/small-test-phased.bam").unwrap();
+    let reads = reads_from_records(bam.records().map(|r| r.unwrap()), false, ReadLength::Query);
+    let from_file = bam_to_reads(
+        "test-data/small-test-phased.bam",
+        2,
+        false,
+        ReadLength::Query,
+        false,
+        &Sampling::default(),
+    );
+    assert_eq!(reads.len(), from_file.len());
+}
+
+#[test]
+fn test_read_length() {
+    use rust_htslib::bam::record::CigarString;
+    let cigar = CigarString(vec![
+        Cigar::HardClip(5),
+        Cigar::SoftClip(10),
+        Cigar::Match(100),
+        Cigar::Ins(5),
+        Cigar::Del(3),
+        Cigar::Equal(20),
+    ]);
+    let seq = vec![b'A'; 135];
+    let mut record = bam::Record::new();
+    record.set(b"read", Some(&cigar), &seq, &[30; 135]);
+    assert_eq!(ReadLength::Query.of(&record), 135);
+    assert_eq!(ReadLength::Original.of(&record), 140);
+    assert_eq!(ReadLength::Aligned.of(&record), 125);
+    assert_eq!(ReadLength::ReferenceSpan.of(&record), 123);
+    // without a stored sequence, only the lengths from the CIGAR are known
+    record.set(b"read", Some(&cigar), &[], &[]);
+    assert_eq!(ReadLength::Query.of(&record), 0);
+    assert_eq!(ReadLength::Original.of(&record), 140);
+    assert_eq!(
+        record_to_read(&record, false, ReadLength::Query),
+        Err(Skip::Length)
+    );
+}
+
+#[test]
+fn test_per_read() {
+    let reads = |threads, per_read| {
+        let reads = bam_to_reads(
+            "test-data/small-test-phased.bam",
+            threads,
+            false,
+            ReadLength::Query,
+            per_read,
+            &Sampling::default(),
+        );
+        (
+            reads.len(),
+            reads_to_histogram(&reads, Axes::new(crate::transform::AccuracyScale::Percent)),
+        )
+    };
+    let (records, _) = reads(2, false);
+    let (single, single_histogram) = reads(1, true);
+    let (parallel, parallel_histogram) = reads(8, true);
+    assert!(single <= records);
+    assert_eq!(single, parallel);
+    assert_eq!(single_histogram, parallel_histogram);
+}
+
+#[test]
+fn test_read_pieces() {
+    use rust_htslib::bam::record::CigarString;
+    let piece = |cigar: Vec<Cigar>, nm: u32| {
+        let cigar = CigarString(cigar);
+        let seq = vec![
+            b'A';
+            cigar
+                .iter()
+                .filter(|c| !matches!(c, Cigar::HardClip(_) | Cigar::Del(_)))
+                .map(|c| c.len() as usize)
+                .sum()
+        ];
+        let mut record = bam::Record::new();
+        record.set(b"read", Some(&cigar), &seq, &vec![30; seq.len()]);
+        record.push_aux(b"NM", Aux::U32(nm)).unwrap();
+        record
+    };
+    // the primary alignment soft clips the part of the read that is in the supplementary alignment
+    let primary = piece(vec![Cigar::Match(600), Cigar::SoftClip(400)], 6);
+    let supplementary = piece(
+        vec![
+            Cigar::HardClip(600),
+            Cigar::Match(399),
+            Cigar::Del(10),
+            Cigar::HardClip(1),
+        ],
+        13,
+    );
+    let mut pieces = ReadPieces::new(String::from("group"), 0);
+    pieces.add(&primary, ReadLength::Query);
+    let mut other = ReadPieces::new(String::new(), 1);
+    other.add(&supplementary, ReadLength::Query);
+    pieces.merge(other);
+    assert_eq!(pieces.alignments, 2);
+    assert_eq!(pieces.group, "group");
+    // the group of the first alignment in the file, in whatever order the workers are merged
+    let mut later = ReadPieces::new(String::from("other"), 5);
+    later.merge(pieces.clone());
+    assert_eq!((later.group.as_str(), later.first), ("group", 0));
+    let read = pieces.read(ReadLength::Query).unwrap();
+    assert_eq!(read.length, 1000);
+    // 6 mismatches, and 3 mismatches and a deletion, in 600 + 400 columns
+    assert!((read.identity - 99.0).abs() < 1e-4);
+    let mut aligned = ReadPieces::new(String::new(), 0);
+    aligned.add(&primary, ReadLength::Aligned);
+    aligned.add(&supplementary, ReadLength::Aligned);
+    assert_eq!(aligned.read(ReadLength::Aligned).unwrap().length, 999);
+}
+
+#[test]
+fn test_parse_timestamp() {
+    let seconds = |timestamp| parse_timestamp(timestamp).unwrap();
+    assert_eq!(seconds("1970-01-01T00:00:00Z"), 0.0);
+    assert_eq!(seconds("2000-03-01T00:00:00+00:00"), 951868800.0);
+    let start = seconds("2023-07-19T18:06:22.154+00:00");
+    assert!((start - 1689789982.154).abs() < 1e-6);
+    // the same moment in other time zones, and a day later
+    assert!((seconds("2023-07-19T20:06:22.154+02:00") - start).abs() < 1e-6);
+    assert!((seconds("2023-07-19T12:06:22.154-0600") - start).abs() < 1e-6);
+    assert!((seconds("2023-07-20T18:06:22.154") - start - 86400.0).abs() < 1e-6);
+    assert!(parse_timestamp("2023-07-19").is_none());
+    assert!(parse_timestamp("2023-13-19T18:06:22Z").is_none());
+    assert!(parse_timestamp("2023-07-19T18:06Z").is_none());
+}
+
+#[test]
+fn test_time_to_histogram() {
+    let axes = Axes::new(crate::transform::AccuracyScale::Percent);
+    let read = |time| TimeAccuracy {
+        time,
+        identity: 95.0,
+        length: 1000,
+    };
+    let histogram = time_to_histogram(&[read(0.0), read(36.0), read(36.01)], axes);
+    assert_eq!(histogram.get(0, axes.transform_accuracy(95.0)), 1.0);
+    assert_eq!(histogram.get(300, axes.transform_accuracy(95.0)), 2.0);
+    assert_eq!(histogram.x_edges()[300], 36.0);
+}
+
+#[test]
+fn test_run_reads_are_ordered() {
+    let file = "test-data/small-test-phased.bam";
+    let single = bam_to_run_reads(file, 1, false, ReadLength::Query, &Sampling::default());
+    let parallel = bam_to_run_reads(file, 8, false, ReadLength::Query, &Sampling::default());
+    assert_eq!(single, parallel);
+    let hours = single.hours.unwrap();
+    assert_eq!(hours.len(), single.reads.len());
+    assert!(hours.windows(2).all(|pair| pair[0] <= pair[1]));
 }
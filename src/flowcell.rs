@@ -0,0 +1,274 @@
+// Map of the channels of an ONT flow cell, colored by the mean accuracy or the number of reads
+// of each channel, to see whether poor reads cluster on a part of the flow cell
+use clap::ValueEnum;
+use image::{Rgb, RgbImage};
+use imageproc::drawing::{draw_filled_rect_mut, draw_text_mut, text_size};
+use imageproc::rect::Rect;
+use log::{info, warn};
+
+use crate::extract_data::{ChannelAccuracy, Channels};
+use crate::intensity::{Scaling, MIN_INTENSITY};
+use crate::plot::{color_at_intensity, color_to_rgb};
+use crate::transform::{AccuracyScale, Axes, PLOT_SIZE};
+use crate::{axis_ticks, BackGround, Color};
+
+const MARGIN: u32 = 20;
+// the space above each flow cell for its label
+const HEADER: u32 = 30;
+// the space below the flow cells for the color scale
+const FOOTER: u32 = 60;
+// the note below the flow cells that the positions of the channels are approximate
+const SCHEMATIC: &str = "schematic channel layout";
+
+/// The value by which the channels are colored
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum ChannelValue {
+    /// the mean accuracy of the reads of the channel, on the accuracy axis
+    Accuracy,
+    /// the number of reads of the channel, transformed with --scale
+    Reads,
+}
+
+/// The arrangement of the channels on a flow cell, which is schematic: the channels are
+/// in the right blocks but not necessarily at the position of their pore in MinKNOW
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Layout {
+    /// 512 channels in 32 columns of 16, also used for the 126 channels of a Flongle
+    MinIon,
+    /// 3000 channels in 12 blocks of 25 rows of 10 channels
+    PromethIon,
+}
+
+impl Layout {
+    /// The smallest flow cell with the highest channel number
+    pub fn from_channels(channels: &Channels) -> Layout {
+        match channels.keys().max() {
+            Some(&channel) if channel > 512 => Layout::PromethIon,
+            _ => Layout::MinIon,
+        }
+    }
+
+    /// The number of columns and rows of channels
+    fn size(self) -> (u32, u32) {
+        match self {
+            Layout::MinIon => (32, 16),
+            Layout::PromethIon => (120, 25),
+        }
+    }
+
+    /// The column and row of a channel, numbered from 1, on the schematic map, or None if it
+    /// is not on the flow cell
+    pub fn position(self, channel: u32) -> Option<(u32, u32)> {
+        let index = channel.checked_sub(1)?;
+        match self {
+            // the channels of each block of 128 run in groups of four from right to left
+            Layout::MinIon if index < 512 => {
+                Some((31 - index % 128 / 4, index / 128 * 4 + index % 4))
+            }
+            Layout::PromethIon if index < 3000 => {
+                Some((index / 250 * 10 + index % 10, index % 250 / 10))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Draw the flow cell of each dataset below each other in its color, with the values
+/// on the same color scale, which is shown below the flow cells
+pub fn render_flowcell(
+    datasets: &[&Channels],
+    labels: &[String],
+    value: ChannelValue,
+    axes: &Axes,
+    background: BackGround,
+    colors: Vec<Color>,
+    scaling: Scaling,
+) -> RgbImage {
+    let layout = datasets
+        .iter()
+        .map(|channels| Layout::from_channels(channels))
+        .max_by_key(|layout| layout.size())
+        .unwrap_or(Layout::MinIon);
+    info!("Drawing the channels of a {layout:?} flow cell");
+    let (columns, rows) = layout.size();
+    let cell = (960 / columns).clamp(4, 24);
+    let (width, panel) = (columns * cell + 2 * MARGIN, rows * cell + HEADER + MARGIN);
+    let height = panel * datasets.len() as u32 + FOOTER;
+    let mut image = match background {
+        BackGround::Black => RgbImage::from_pixel(width, height, Rgb([0, 0, 0])),
+        BackGround::White => RgbImage::from_pixel(width, height, Rgb([255, 255, 255])),
+    };
+    let empty = match background {
+        BackGround::Black => Rgb([40, 40, 40]),
+        BackGround::White => Rgb([225, 225, 225]),
+    };
+    let limits = limits(datasets, scaling);
+    let font = axis_ticks::load_font();
+    let text = axis_ticks::tick_color(background);
+    for (index, (channels, color)) in datasets.iter().zip(&colors).enumerate() {
+        let top = panel * index as u32 + HEADER;
+        let label = match (labels.get(index), value) {
+            (Some(label), _) => label.clone(),
+            (None, ChannelValue::Accuracy) => String::from("mean accuracy per channel"),
+            (None, ChannelValue::Reads) => String::from("reads per channel"),
+        };
+        let label_color = if labels.is_empty() {
+            text
+        } else {
+            color_to_rgb(*color)
+        };
+        draw_text_mut(
+            &mut image,
+            label_color,
+            MARGIN as i32,
+            top as i32 - 26,
+            22.0,
+            &font,
+            &label,
+        );
+        for channel in 1..=columns * rows {
+            let (column, row) = layout.position(channel).expect("channel on the flow cell");
+            let fill = match channels.get(&channel) {
+                Some(reads) => {
+                    let intensity = intensity(reads, value, axes, limits, scaling);
+                    color_at_intensity(*color, background, intensity as u8)
+                }
+                None => empty,
+            };
+            let rect = Rect::at((MARGIN + column * cell) as i32, (top + row * cell) as i32)
+                .of_size(cell - 1, cell - 1);
+            draw_filled_rect_mut(&mut image, rect, fill);
+        }
+        let outside = channels
+            .keys()
+            .filter(|c| layout.position(**c).is_none())
+            .count();
+        if outside > 0 {
+            warn!("Skipped {outside} channels that are not on a {layout:?} flow cell");
+        }
+    }
+    add_color_scale(&mut image, value, axes, limits, background, &colors);
+    image
+}
+
+// the intensity (0-255) of a channel, with the accuracy as on the y-axis of the heatmaps,
+// such that channels with reads stay visible, and the reads as the counts of a heatmap
+fn intensity(
+    reads: &ChannelAccuracy,
+    value: ChannelValue,
+    axes: &Axes,
+    limits: (f64, f64),
+    scaling: Scaling,
+) -> f64 {
+    match value {
+        ChannelValue::Accuracy => {
+            let position = axes.transform_accuracy(reads.mean_identity()) as f64;
+            ((1.0 - position / PLOT_SIZE as f64) * 255.0).max(MIN_INTENSITY)
+        }
+        ChannelValue::Reads => scaling.intensity(reads.reads as f64, limits),
+    }
+}
+
+// the lowest and highest number of reads on the color scale, by default of any channel,
+// as for the counts of the bins of a heatmap
+fn limits(datasets: &[&Channels], scaling: Scaling) -> (f64, f64) {
+    scaling.limits_of_counts(
+        datasets
+            .iter()
+            .flat_map(|channels| channels.values())
+            .map(|reads| reads.reads as f64),
+    )
+}
+
+// a gradient for each color, with the values at its ends
+fn add_color_scale(
+    image: &mut RgbImage,
+    value: ChannelValue,
+    axes: &Axes,
+    limits: (f64, f64),
+    background: BackGround,
+    colors: &[Color],
+) {
+    let top = image.height() - FOOTER + 10;
+    let left = MARGIN + 60;
+    for (index, color) in colors.iter().enumerate() {
+        for x in 0..256 {
+            let rect = Rect::at((left + x) as i32, (top + 8 * index as u32) as i32).of_size(1, 8);
+            draw_filled_rect_mut(image, rect, color_at_intensity(*color, background, x as u8));
+        }
+    }
+    let (low, high) = match value {
+        ChannelValue::Accuracy => match axes.scale {
+            AccuracyScale::Percent => (
+                format!("{}%", axes.min_accuracy),
+                format!("{}%", axes.max_accuracy),
+            ),
+            AccuracyScale::Phred => (
+                format!("Q{}", axes.min_accuracy),
+                format!("Q{}", axes.max_accuracy),
+            ),
+        },
+        ChannelValue::Reads => (format!("{}", limits.0), format!("{} reads", limits.1)),
+    };
+    let font = axis_ticks::load_font();
+    let text = axis_ticks::tick_color(background);
+    let (low_width, _height) = text_size(20.0, &font, &low);
+    let ycoord = top as i32 + 4 * colors.len() as i32 - 12;
+    draw_text_mut(
+        image,
+        text,
+        left as i32 - 8 - low_width as i32,
+        ycoord,
+        20.0,
+        &font,
+        &low,
+    );
+    draw_text_mut(image, text, left as i32 + 264, ycoord, 20.0, &font, &high);
+    let (note_width, _height) = text_size(20.0, &font, SCHEMATIC);
+    let xcoord = image.width() as i32 - MARGIN as i32 - note_width as i32;
+    draw_text_mut(image, text, xcoord, ycoord, 20.0, &font, SCHEMATIC);
+}
+
+#[test]
+fn test_layout() {
+    // the first channel of each block is the top right channel of the block
+    assert_eq!(Layout::MinIon.position(1), Some((31, 0)));
+    assert_eq!(Layout::MinIon.position(4), Some((31, 3)));
+    assert_eq!(Layout::MinIon.position(5), Some((30, 0)));
+    assert_eq!(Layout::MinIon.position(129), Some((31, 4)));
+    assert_eq!(Layout::MinIon.position(512), Some((0, 15)));
+    assert_eq!(Layout::MinIon.position(513), None);
+    assert_eq!(Layout::MinIon.position(0), None);
+    assert_eq!(Layout::PromethIon.position(251), Some((10, 0)));
+    assert_eq!(Layout::PromethIon.position(3000), Some((119, 24)));
+    // every channel has its own position
+    for layout in [Layout::MinIon, Layout::PromethIon] {
+        let (columns, rows) = layout.size();
+        let positions = (1..=columns * rows)
+            .filter_map(|c| layout.position(c))
+            .collect::<std::collections::HashSet<_>>();
+        assert_eq!(positions.len(), (columns * rows) as usize);
+    }
+}
+
+#[test]
+fn test_limits() {
+    // a channel with 1000 reads and 99 channels with a single read
+    let channels = (1..=100)
+        .map(|channel| {
+            let reads = if channel == 1 { 1000 } else { 1 };
+            let accuracy = ChannelAccuracy {
+                reads,
+                identity: 95.0 * reads as f64,
+                bases: 1000 * reads as u64,
+            };
+            (channel, accuracy)
+        })
+        .collect::<Channels>();
+    assert_eq!(limits(&[&channels], Scaling::default()), (0.0, 1000.0));
+    let quantile = Scaling {
+        vmax_quantile: Some(0.99),
+        ..Default::default()
+    };
+    assert_eq!(limits(&[&channels], quantile), (0.0, 1.0));
+}
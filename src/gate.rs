@@ -0,0 +1,247 @@
+// A rectangle on the length-accuracy plane, e.g. the acceptance criteria of a sequencing run,
+// with the fraction of the reads and bases of each dataset that fall inside it
+use image::{Rgb, RgbImage};
+use imageproc::drawing::{draw_hollow_rect_mut, draw_text, text_size};
+use imageproc::rect::Rect;
+use std::ops::RangeInclusive;
+
+use crate::extract_data::ReadAccuracy;
+use crate::guides::{self, LabelBox, Line};
+use crate::histogram::{Histogram2D, HistogramKind};
+use crate::transform::{Axes, PLOT_SIZE};
+
+/// The limits of the gate, inclusive, with the accuracy as identity in percent
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Gate {
+    pub min_length: Option<usize>,
+    pub max_length: Option<usize>,
+    pub min_accuracy: Option<f32>,
+    pub max_accuracy: Option<f32>,
+}
+
+/// Parse the --gate argument: comma-separated conditions on the length and the accuracy,
+/// with the values as for --vline and --hline, e.g. length>=10kb,accuracy>=Q20
+pub fn parse_gate(value: &str) -> Result<Gate, String> {
+    let mut gate = Gate::default();
+    for condition in value.split(',') {
+        let (variable, at_least, threshold) = if let Some((v, t)) = condition.split_once(">=") {
+            (v, true, t)
+        } else if let Some((v, t)) = condition.split_once("<=") {
+            (v, false, t)
+        } else {
+            return Err(format!(
+                "Invalid condition {condition}, expected e.g. length>=10kb or accuracy<=Q30"
+            ));
+        };
+        let line = match variable.trim() {
+            "length" => guides::parse_vline(threshold.trim())?.line,
+            "accuracy" => guides::parse_hline(threshold.trim())?.line,
+            _ => {
+                return Err(format!(
+                    "Invalid condition {condition}, expected length or accuracy"
+                ))
+            }
+        };
+        match (line, at_least) {
+            (Line::Length(length), true) => gate.min_length = Some(length),
+            (Line::Length(length), false) => gate.max_length = Some(length),
+            (Line::Accuracy(accuracy), true) => gate.min_accuracy = Some(accuracy),
+            (Line::Accuracy(accuracy), false) => gate.max_accuracy = Some(accuracy),
+        }
+    }
+    Ok(gate)
+}
+
+impl Gate {
+    /// The bins of the gate along the x- and the y-axis, with the transformations of the reads,
+    /// such that a read is in the gate if its bin is
+    pub fn bins(&self, axes: &Axes) -> (RangeInclusive<usize>, RangeInclusive<usize>) {
+        let x = self.min_length.map_or(0, |l| axes.transform_length(l))
+            ..=self
+                .max_length
+                .map_or(PLOT_SIZE, |l| axes.transform_length(l));
+        // the y-axis is counted from the top, with the highest accuracy
+        let y = self.max_accuracy.map_or(0, |a| axes.transform_accuracy(a))
+            ..=self
+                .min_accuracy
+                .map_or(PLOT_SIZE, |a| axes.transform_accuracy(a));
+        (x, y)
+    }
+
+    /// Whether a read is in the gate, including the reads at the limits
+    pub fn contains(&self, read: &ReadAccuracy) -> bool {
+        let length = read.length as usize;
+        self.min_length.is_none_or(|l| length >= l)
+            && self.max_length.is_none_or(|l| length <= l)
+            && self.min_accuracy.is_none_or(|a| read.identity >= a)
+            && self.max_accuracy.is_none_or(|a| read.identity <= a)
+    }
+
+    /// The exact fraction of the reads and of the bases in the gate
+    pub fn read_fractions(&self, reads: &[ReadAccuracy]) -> Option<(f64, f64)> {
+        if reads.is_empty() {
+            return None;
+        }
+        let (mut inside, mut bases, mut total_bases) = (0, 0, 0);
+        for read in reads {
+            total_bases += u64::from(read.length);
+            if self.contains(read) {
+                inside += 1;
+                bases += u64::from(read.length);
+            }
+        }
+        Some((
+            inside as f64 / reads.len() as f64,
+            bases as f64 / total_bases as f64,
+        ))
+    }
+
+    /// The fraction of the reads and of the bases in the gate estimated from the bins, for
+    /// histograms without the reads, e.g. from kyber files, with the length of the reads
+    /// estimated from the middle of their bin
+    pub fn fractions(&self, histogram: &Histogram2D) -> Option<(f64, f64)> {
+        if histogram.kind() != HistogramKind::LengthAccuracy || histogram.is_empty() {
+            return None;
+        }
+        let (xs, ys) = self.bins(&histogram.axes());
+        let edges = histogram.x_edges();
+        let (mut reads, mut bases, mut total_reads, mut total_bases) = (0.0, 0.0, 0.0, 0.0);
+        for ((x, y), count) in histogram.iter() {
+            let length = f64::from((edges[x] * edges[x + 1]).sqrt());
+            total_reads += count;
+            total_bases += count * length;
+            if xs.contains(&x) && ys.contains(&y) {
+                reads += count;
+                bases += count * length;
+            }
+        }
+        Some((reads / total_reads, bases / total_bases))
+    }
+}
+
+/// Shade the gate and label it with the fractions of the reads and bases of each dataset,
+/// in the color of that dataset, with the labels added to those that later labels avoid
+pub fn add_gate(
+    mut image: RgbImage,
+    axes: &Axes,
+    background: crate::BackGround,
+    gate: &Gate,
+    fractions: &[((f64, f64), Rgb<u8>)],
+    labels: &mut Vec<LabelBox>,
+) -> RgbImage {
+    let (xs, ys) = gate.bins(axes);
+    if xs.is_empty() || ys.is_empty() {
+        log::warn!("The gate is empty on these axes");
+        return image;
+    }
+    let shade = crate::axis_ticks::tick_color(background);
+    for y in ys.clone() {
+        for x in xs.clone() {
+            let pixel = image.get_pixel_mut(x as u32, y as u32);
+            for (channel, shade) in pixel.0.iter_mut().zip(shade.0) {
+                *channel = (0.85 * f64::from(*channel) + 0.15 * f64::from(shade)) as u8;
+            }
+        }
+    }
+    let (left, top) = (*xs.start() as i32, *ys.start() as i32);
+    let (width, height) = (xs.count() as u32, ys.count() as u32);
+    draw_hollow_rect_mut(
+        &mut image,
+        Rect::at(left, top).of_size(width, height),
+        shade,
+    );
+    // the labels are in the bottom left corner of the gate, above the labels of the ticks
+    let font = crate::axis_ticks::load_font();
+    let bottom = (top + height as i32).min(550) - 22 * fractions.len() as i32;
+    for (index, ((reads, bases), color)) in fractions.iter().enumerate() {
+        let label = format!(
+            "{:.1}% of reads, {:.1}% of bases",
+            reads * 100.0,
+            bases * 100.0
+        );
+        let (label_width, _height) = text_size(20.0, &font, &label);
+        let xcoord = (left + 6).min(PLOT_SIZE as i32 - 6 - label_width as i32);
+        let ycoord = (bottom + 22 * index as i32).max(0);
+        let (xcoord, ycoord) =
+            guides::place_label(labels, (xcoord, ycoord), (label_width, 22), (0, -22));
+        image = draw_text(&image, *color, xcoord, ycoord, 20.0, &font, &label);
+    }
+    image
+}
+
+#[test]
+fn test_parse_gate() {
+    let gate = parse_gate("length>=10kb,accuracy>=Q20").unwrap();
+    assert_eq!(gate.min_length, Some(10000));
+    assert_eq!(gate.min_accuracy, Some(99.0));
+    assert_eq!(gate.max_length, None);
+    let gate = parse_gate("length<=5000, accuracy>=95%,accuracy<=99").unwrap();
+    assert_eq!(gate.max_length, Some(5000));
+    assert_eq!(gate.min_accuracy, Some(95.0));
+    assert_eq!(gate.max_accuracy, Some(99.0));
+    assert!(parse_gate("length>10kb").is_err());
+    assert!(parse_gate("quality>=Q20").is_err());
+    assert!(parse_gate("accuracy>=Qx").is_err());
+}
+
+#[test]
+fn test_fractions() {
+    let axes = Axes::new(crate::transform::AccuracyScale::Percent);
+    let mut histogram = Histogram2D::new(HistogramKind::LengthAccuracy, axes);
+    let gate = parse_gate("length>=10kb,accuracy>=95%").unwrap();
+    // three long and accurate reads, a long inaccurate read and a short accurate read
+    for _ in 0..3 {
+        histogram.add(axes.transform_length(20000), axes.transform_accuracy(98.0));
+    }
+    histogram.add(axes.transform_length(20000), axes.transform_accuracy(80.0));
+    histogram.add(axes.transform_length(100), axes.transform_accuracy(98.0));
+    let (reads, bases) = gate.fractions(&histogram).unwrap();
+    assert_eq!(reads, 0.6);
+    assert!((bases - 60000.0 / 80100.0).abs() < 1e-3);
+    // the reads at the threshold are in the gate
+    let (x, y) = gate.bins(&axes);
+    assert!(x.contains(&axes.transform_length(10000)));
+    assert!(y.contains(&axes.transform_accuracy(95.0)));
+    assert!(!y.contains(&(axes.transform_accuracy(95.0) + 1)));
+}
+
+#[test]
+fn test_read_fractions() {
+    let axes = Axes::new(crate::transform::AccuracyScale::Percent);
+    let gate = parse_gate("length<=10kb,accuracy>=95%").unwrap();
+    let read = |length, identity| ReadAccuracy { length, identity };
+    let reads = [
+        read(10000, 95.0),
+        read(10200, 98.0),
+        read(5000, 99.0),
+        read(5000, 80.0),
+    ];
+    // a read just above the threshold is in the same bin as the threshold, but not in the gate
+    assert!(gate.bins(&axes).0.contains(&axes.transform_length(10200)));
+    let (reads, bases) = gate.read_fractions(&reads).unwrap();
+    assert_eq!(reads, 0.5);
+    assert_eq!(bases, 15000.0 / 30200.0);
+    assert_eq!(gate.read_fractions(&[]), None);
+}
+
+#[test]
+fn test_labels_with_guides() {
+    let axes = Axes::new(crate::transform::AccuracyScale::Percent);
+    let gate = parse_gate("length>=10kb").unwrap();
+    let red = Rgb([255, 0, 0]);
+    let mut labels = vec![];
+    let image = add_gate(
+        RgbImage::new(601, 601),
+        &axes,
+        crate::BackGround::Black,
+        &gate,
+        &[((0.5, 0.6), red)],
+        &mut labels,
+    );
+    // the label of a line at the edge of the gate is moved above the label of the gate
+    let guides = [(guides::parse_vline("10kb").unwrap(), Some(red))];
+    guides::add_guides(image, &axes, crate::BackGround::Black, &guides, &mut labels);
+    assert_eq!(labels.len(), 2);
+    let ((x, y, w, h), (ox, oy, ow, oh)) = (labels[0], labels[1]);
+    assert!(x >= ox + ow || ox >= x + w || y >= oy + oh || oy >= y + h);
+}
@@ -0,0 +1,229 @@
+// Labelled reference lines at a read length or an accuracy, positioned with the same
+// transformations as the bins such that these line up with the reads in the plot
+use image::{Rgb, RgbImage};
+use imageproc::drawing::{draw_text, text_size};
+use log::warn;
+
+use crate::histogram::Histogram2D;
+use crate::transform::{accuracy_to_phred, phred_to_accuracy, AccuracyScale, Axes, PLOT_SIZE};
+
+/// The position and size of a label on the plot, as x, y, width and height, such that
+/// the labels that are drawn later can be moved to not overlap with it
+pub type LabelBox = (i32, i32, i32, i32);
+
+/// The position of a guide line
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Line {
+    /// a vertical line at a read length
+    Length(usize),
+    /// a horizontal line at an accuracy, as identity in percent
+    Accuracy(f32),
+}
+
+/// A line with its label
+#[derive(Clone, Debug, PartialEq)]
+pub struct Guide {
+    pub line: Line,
+    pub label: String,
+}
+
+impl Guide {
+    /// A vertical line at the read length N50 of the histogram
+    pub fn n50(histogram: &Histogram2D) -> Option<Guide> {
+        histogram.length_n50().map(|n50| Guide {
+            line: Line::Length(n50 as usize),
+            label: format!("N50 {}", format_length(n50)),
+        })
+    }
+
+    /// A horizontal line at the median accuracy of the histogram, on the scale of its axis
+    pub fn median_accuracy(histogram: &Histogram2D) -> Option<Guide> {
+        histogram.median_accuracy().map(|median| Guide {
+            line: Line::Accuracy(median),
+            label: match histogram.scale() {
+                AccuracyScale::Percent => format!("median {median:.1}%"),
+                AccuracyScale::Phred => format!("median Q{:.1}", accuracy_to_phred(median)),
+            },
+        })
+    }
+}
+
+fn format_length(length: f32) -> String {
+    if length >= 1e6 {
+        format!("{:.1} Mb", length / 1e6)
+    } else if length >= 1e3 {
+        format!("{:.1} kb", length / 1e3)
+    } else {
+        format!("{length:.0} bp")
+    }
+}
+
+/// Parse the --vline argument: a read length, optionally with a unit as in 10kb or 1.5Mb
+pub fn parse_vline(value: &str) -> Result<Guide, String> {
+    let lowercase = value.to_lowercase();
+    let number = lowercase.trim_end_matches('b');
+    let (number, unit) = match number.chars().last() {
+        Some('k') => (&number[..number.len() - 1], 1e3),
+        Some('m') => (&number[..number.len() - 1], 1e6),
+        Some('g') => (&number[..number.len() - 1], 1e9),
+        _ => (number, 1.0),
+    };
+    match number.parse::<f64>() {
+        Ok(length) if length >= 1.0 && length * unit >= 1.0 => Ok(Guide {
+            line: Line::Length((length * unit).round() as usize),
+            label: value.to_string(),
+        }),
+        _ => Err(format!(
+            "Invalid read length {value}, expected e.g. 5000, 10kb or 1.5Mb"
+        )),
+    }
+}
+
+/// Parse the --hline argument: an accuracy in percent as in 95 or 95%, or a phred score as in Q20
+pub fn parse_hline(value: &str) -> Result<Guide, String> {
+    let accuracy = match value.strip_prefix('Q') {
+        Some(phred) => phred
+            .parse::<f32>()
+            .ok()
+            .filter(|p| *p >= 0.0)
+            .map(phred_to_accuracy),
+        None => value
+            .trim_end_matches('%')
+            .parse::<f32>()
+            .ok()
+            .filter(|a| (0.0..=100.0).contains(a)),
+    };
+    match accuracy {
+        Some(accuracy) => Ok(Guide {
+            line: Line::Accuracy(accuracy),
+            label: value.to_string(),
+        }),
+        None => Err(format!(
+            "Invalid accuracy {value}, expected a percentage as in 95% or a phred score as in Q20"
+        )),
+    }
+}
+
+/// Draw dashed lines with their label in their color, or in the color of the ticks without one,
+/// leaving out lines outside of the axes, with the labels moved away from the labels drawn before
+pub fn add_guides(
+    mut image: RgbImage,
+    axes: &Axes,
+    background: crate::BackGround,
+    guides: &[(Guide, Option<Rgb<u8>>)],
+    labels: &mut Vec<LabelBox>,
+) -> RgbImage {
+    let font = crate::axis_ticks::load_font();
+    for (guide, color) in guides {
+        let color = color.unwrap_or_else(|| crate::axis_ticks::tick_color(background));
+        let (width, _height) = text_size(20.0, &font, &guide.label);
+        match guide.line {
+            Line::Length(length) => {
+                if length == 0 || length > axes.max_length {
+                    warn!("Guide {} is outside of the length axis", guide.label);
+                    continue;
+                }
+                let x = axes.transform_length(length) as u32;
+                for y in (0..=PLOT_SIZE as u32).filter(|y| y % 8 < 4) {
+                    image.put_pixel(x, y, color);
+                }
+                // the label is next to the line, above the labels of the ticks at the bottom,
+                // and on the left of the line if it does not fit on the right
+                let left = x as i32 + 4 + width as i32 > PLOT_SIZE as i32;
+                let xcoord = if left {
+                    x as i32 - 4 - width as i32
+                } else {
+                    x as i32 + 4
+                };
+                let (xcoord, ycoord) = place_label(labels, (xcoord, 530), (width, 22), (0, -22));
+                image = draw_text(&image, color, xcoord, ycoord, 20.0, &font, &guide.label);
+            }
+            Line::Accuracy(accuracy) => {
+                let value = match axes.scale {
+                    AccuracyScale::Percent => accuracy,
+                    AccuracyScale::Phred => accuracy_to_phred(accuracy),
+                };
+                if value < axes.min_accuracy || value > axes.max_accuracy {
+                    warn!("Guide {} is outside of the accuracy axis", guide.label);
+                    continue;
+                }
+                let y = axes.transform_accuracy(accuracy) as u32;
+                for x in (0..=PLOT_SIZE as u32).filter(|x| x % 8 < 4) {
+                    image.put_pixel(x, y, color);
+                }
+                // the label is above the line on the right, or below the line at the top
+                let ycoord = if y < 24 { y as i32 + 4 } else { y as i32 - 22 };
+                let (xcoord, ycoord) =
+                    place_label(labels, (585 - width as i32, ycoord), (width, 22), (-10, 0));
+                image = draw_text(&image, color, xcoord, ycoord, 20.0, &font, &guide.label);
+            }
+        }
+    }
+    image
+}
+
+// the labels of lines that are close together are moved by a step until these do not overlap
+pub(crate) fn place_label(
+    labels: &mut Vec<LabelBox>,
+    (mut x, mut y): (i32, i32),
+    (width, height): (u32, i32),
+    (dx, dy): (i32, i32),
+) -> (i32, i32) {
+    let width = width as i32;
+    let overlaps = |x: i32, y: i32, labels: &[LabelBox]| {
+        labels.iter().any(|(lx, ly, lw, lh)| {
+            x < lx + lw && *lx < x + width && y < ly + lh && *ly < y + height
+        })
+    };
+    while overlaps(x, y, labels) {
+        x += dx;
+        y += dy;
+    }
+    labels.push((x, y, width, height));
+    (x, y)
+}
+
+#[test]
+fn test_parse_vline() {
+    assert_eq!(parse_vline("10kb").unwrap().line, Line::Length(10000));
+    assert_eq!(parse_vline("10k").unwrap().line, Line::Length(10000));
+    assert_eq!(parse_vline("1.5Mb").unwrap().line, Line::Length(1500000));
+    assert_eq!(parse_vline("5000").unwrap().line, Line::Length(5000));
+    assert_eq!(parse_vline("10kb").unwrap().label, "10kb");
+    assert!(parse_vline("kb").is_err());
+    assert!(parse_vline("0").is_err());
+    assert!(parse_vline("10xb").is_err());
+}
+
+#[test]
+fn test_parse_hline() {
+    assert_eq!(parse_hline("95").unwrap().line, Line::Accuracy(95.0));
+    assert_eq!(parse_hline("95%").unwrap().line, Line::Accuracy(95.0));
+    assert_eq!(parse_hline("Q20").unwrap().line, Line::Accuracy(99.0));
+    assert!(parse_hline("101").is_err());
+    assert!(parse_hline("Qx").is_err());
+}
+
+#[test]
+fn test_add_guides() {
+    let axes = Axes::new(AccuracyScale::Phred);
+    let red = Rgb([255, 0, 0]);
+    let guides = [
+        (parse_vline("10kb").unwrap(), Some(red)),
+        (parse_hline("Q20").unwrap(), Some(red)),
+        (parse_hline("Q50").unwrap(), Some(red)),
+    ];
+    let image = add_guides(
+        RgbImage::new(601, 601),
+        &axes,
+        crate::BackGround::Black,
+        &guides,
+        &mut vec![],
+    );
+    // the lines are at the same position as the bins of reads of 10 kb and Q20
+    let x = axes.transform_length(10000) as u32;
+    let y = axes.transform_accuracy(99.0) as u32;
+    assert_eq!(image.get_pixel(x, 0), &red);
+    assert_eq!(image.get_pixel(0, y), &red);
+    assert_eq!(image.get_pixel(x + 1, 0), &Rgb([0, 0, 0]));
+}
@@ -0,0 +1,401 @@
+use crate::transform::{AccuracyScale, Axes, PLOT_SIZE};
+
+// every pixel of the plot is a bin, including the last one at PLOT_SIZE
+const BINS: usize = PLOT_SIZE + 1;
+
+/// The variables on the axes of a histogram
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum HistogramKind {
+    /// read length (x) against accuracy (y)
+    LengthAccuracy,
+    /// accuracy predicted from the base qualities (x) against the observed accuracy (y)
+    Calibration,
+    /// time into the sequencing run (x) against accuracy (y)
+    TimeAccuracy,
+}
+
+/// Dense two-dimensional histogram with a bin for each pixel of the plot,
+/// with x as the column and y as the row of the image
+#[derive(Clone, Debug, PartialEq)]
+pub struct Histogram2D {
+    kind: HistogramKind,
+    axes: Axes,
+    x_edges: Vec<f32>,
+    y_edges: Vec<f32>,
+    counts: Vec<f64>,
+    total: u64,
+}
+
+impl Histogram2D {
+    pub fn new(kind: HistogramKind, axes: Axes) -> Histogram2D {
+        // a bin spans from its edge to the edge of the next bin
+        let x_edges = (0..=BINS)
+            .map(|bin| match kind {
+                HistogramKind::LengthAccuracy => axes.length_bin_edge(bin as f32),
+                // the predicted accuracy axis is mirrored, see transform_predicted_accuracy
+                HistogramKind::Calibration => axes.accuracy_bin_edge((BINS - bin) as f32),
+                HistogramKind::TimeAccuracy => axes.time_bin_edge(bin as f32),
+            })
+            .collect();
+        let y_edges = (0..=BINS)
+            .map(|bin| axes.accuracy_bin_edge(bin as f32))
+            .collect();
+        Histogram2D {
+            kind,
+            axes,
+            x_edges,
+            y_edges,
+            counts: vec![0.0; BINS * BINS],
+            total: 0,
+        }
+    }
+
+    /// Histogram with the counts of bins that were saved before, e.g. in a kyber file
+    pub(crate) fn from_bins<I>(kind: HistogramKind, axes: Axes, total: u64, bins: I) -> Histogram2D
+    where
+        I: IntoIterator<Item = ((usize, usize), f64)>,
+    {
+        let mut histogram = Histogram2D::new(kind, axes);
+        for ((x, y), count) in bins {
+            histogram.counts[bin_index(x, y)] = count;
+        }
+        histogram.total = total;
+        histogram
+    }
+
+    pub fn kind(&self) -> HistogramKind {
+        self.kind
+    }
+
+    pub fn axes(&self) -> Axes {
+        self.axes
+    }
+
+    pub fn scale(&self) -> AccuracyScale {
+        self.axes.scale
+    }
+
+    /// The read length, predicted accuracy or time at the edges of the bins along the x-axis
+    pub fn x_edges(&self) -> &[f32] {
+        &self.x_edges
+    }
+
+    /// The accuracy at the edges of the bins along the y-axis
+    pub fn y_edges(&self) -> &[f32] {
+        &self.y_edges
+    }
+
+    /// Number of reads that were added
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.total == 0
+    }
+
+    /// Count a read in the bin at plot coordinates (x, y)
+    pub fn add(&mut self, x: usize, y: usize) {
+        let index = bin_index(x, y);
+        self.counts[index] += 1.0;
+        self.total += 1;
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> f64 {
+        self.counts[bin_index(x, y)]
+    }
+
+    /// Add the counts of another histogram with the same axes, which the caller has to check
+    pub fn merge(&mut self, other: &Histogram2D) {
+        assert!(
+            self.kind == other.kind && self.axes == other.axes,
+            "histograms with different axes cannot be merged"
+        );
+        for (count, other_count) in self.counts.iter_mut().zip(&other.counts) {
+            *count += other_count;
+        }
+        self.total += other.total;
+    }
+
+    /// Multiply the counts in each bin, e.g. to compare datasets with a different number of reads
+    pub fn scale_counts(&mut self, factor: f64) {
+        for count in self.counts.iter_mut() {
+            *count *= factor;
+        }
+    }
+
+    /// Smooth the counts with a Gaussian kernel with a standard deviation of sigma bins,
+    /// such that sparse datasets do not look speckled
+    pub fn smooth(&mut self, sigma: f64) {
+        let radius = (3.0 * sigma).ceil() as usize;
+        let kernel = (0..=2 * radius)
+            .map(|i| (-((i as f64 - radius as f64).powi(2)) / (2.0 * sigma * sigma)).exp())
+            .collect::<Vec<f64>>();
+        let sum = kernel.iter().sum::<f64>();
+        let kernel = kernel.iter().map(|w| w / sum).collect::<Vec<f64>>();
+        // the count of a single read, which is below 1 after the normalization of a dataset
+        let single = self.counts.iter().sum::<f64>() / self.total.max(1) as f64;
+        // the kernel is separable, first along the rows and then along the columns
+        let mut rows = vec![0.0; BINS * BINS];
+        for (index, count) in self.counts.iter().enumerate().filter(|(_i, c)| **c > 0.0) {
+            let (x, y) = (index % BINS, index / BINS);
+            for (i, weight) in kernel.iter().enumerate() {
+                if let Some(x) = (x + i).checked_sub(radius).filter(|x| *x < BINS) {
+                    rows[bin_index(x, y)] += count * weight;
+                }
+            }
+        }
+        self.counts.iter_mut().for_each(|c| *c = 0.0);
+        for (index, count) in rows.iter().enumerate().filter(|(_i, c)| **c > 0.0) {
+            let (x, y) = (index % BINS, index / BINS);
+            for (i, weight) in kernel.iter().enumerate() {
+                if let Some(y) = (y + i).checked_sub(radius).filter(|y| *y < BINS) {
+                    self.counts[bin_index(x, y)] += count * weight;
+                }
+            }
+        }
+        // less than a single read contributes at a distance of three sigma, which
+        // is left out such that the smoothed bins are not cut off in squares
+        let min_count = single * kernel[radius] * kernel[radius] * (-4.5f64).exp();
+        for count in self.counts.iter_mut().filter(|c| **c < min_count) {
+            *count = 0.0;
+        }
+    }
+
+    /// A histogram of a random selection of this number of reads, which is the same for every run
+    pub fn downsample(&self, reads: u64) -> Histogram2D {
+        let mut downsampled = Histogram2D::new(self.kind, self.axes);
+        let mut remaining = self.counts.iter().sum::<f64>() as u64;
+        let selected = reads.min(remaining);
+        let mut needed = selected;
+        let mut state = 0;
+        // selection sampling: each read is selected with the probability of
+        // the number of reads that is still needed out of those that remain
+        for (index, count) in self.counts.iter().enumerate() {
+            for _ in 0..*count as u64 {
+                if splitmix64(&mut state) % remaining < needed {
+                    downsampled.counts[index] += 1.0;
+                    needed -= 1;
+                }
+                remaining -= 1;
+            }
+        }
+        downsampled.total = selected;
+        downsampled
+    }
+
+    /// The read length N50 estimated from the bins, with the reads at the middle of their bin,
+    /// such that it is at the same position as these reads in the plot
+    pub fn length_n50(&self) -> Option<f32> {
+        if self.kind != HistogramKind::LengthAccuracy || self.is_empty() {
+            return None;
+        }
+        let middle = |x: usize| (self.x_edges[x] * self.x_edges[x + 1]).sqrt();
+        let mut bases = vec![0.0; BINS];
+        for ((x, _y), count) in self.iter() {
+            bases[x] += count * f64::from(middle(x));
+        }
+        let half = bases.iter().sum::<f64>() / 2.0;
+        let mut cumulative = 0.0;
+        // the length of the reads in the bin at which half of the bases is reached,
+        // starting from the longest reads
+        (0..BINS)
+            .rev()
+            .map(middle)
+            .zip(bases.iter().rev())
+            .find_map(|(length, b)| {
+                cumulative += b;
+                (cumulative >= half).then_some(length)
+            })
+    }
+
+    /// The median accuracy in percent estimated from the bins, at the middle of its bin
+    pub fn median_accuracy(&self) -> Option<f32> {
+        if self.kind != HistogramKind::LengthAccuracy || self.is_empty() {
+            return None;
+        }
+        let mut reads = vec![0.0; BINS];
+        for ((_x, y), count) in self.iter() {
+            reads[y] += count;
+        }
+        let half = reads.iter().sum::<f64>() / 2.0;
+        let mut cumulative = 0.0;
+        reads.iter().enumerate().find_map(|(y, r)| {
+            cumulative += r;
+            (cumulative >= half).then(|| (self.y_edges[y] + self.y_edges[y + 1]) / 2.0)
+        })
+    }
+
+    /// The highest count of all bins
+    pub fn max(&self) -> f64 {
+        self.counts.iter().copied().fold(0.0, f64::max)
+    }
+
+    /// Iterate over the bins with a count, as ((x, y), count)
+    pub fn iter(&self) -> impl Iterator<Item = ((usize, usize), f64)> + '_ {
+        self.counts
+            .iter()
+            .enumerate()
+            .filter(|(_index, count)| **count > 0.0)
+            .map(|(index, count)| ((index % BINS, index / BINS), *count))
+    }
+}
+
+// pseudorandom numbers from a seed, to make the downsampling reproducible
+fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9e3779b97f4a7c15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+fn bin_index(x: usize, y: usize) -> usize {
+    if x >= BINS || y >= BINS {
+        panic!("Bin ({x}, {y}) is outside of the {BINS}x{BINS} histogram");
+    }
+    y * BINS + x
+}
+
+#[test]
+fn test_add_and_iterate() {
+    let mut hist = Histogram2D::new(
+        HistogramKind::LengthAccuracy,
+        Axes::new(AccuracyScale::Percent),
+    );
+    hist.add(300, 10);
+    hist.add(300, 10);
+    hist.add(0, 600);
+    assert_eq!(hist.total(), 3);
+    assert_eq!(hist.get(300, 10), 2.0);
+    assert_eq!(hist.max(), 2.0);
+    assert_eq!(
+        hist.iter().collect::<Vec<_>>(),
+        vec![((300, 10), 2.0), ((0, 600), 1.0)]
+    );
+}
+
+#[test]
+fn test_merge() {
+    let mut hist = Histogram2D::new(
+        HistogramKind::LengthAccuracy,
+        Axes::new(AccuracyScale::Phred),
+    );
+    hist.add(1, 2);
+    let mut other = Histogram2D::new(
+        HistogramKind::LengthAccuracy,
+        Axes::new(AccuracyScale::Phred),
+    );
+    other.add(1, 2);
+    other.add(3, 4);
+    hist.merge(&other);
+    assert_eq!(hist.total(), 3);
+    assert_eq!(hist.get(1, 2), 2.0);
+    assert_eq!(hist.get(3, 4), 1.0);
+}
+
+#[test]
+#[should_panic]
+fn test_merge_different_scale() {
+    let mut hist = Histogram2D::new(
+        HistogramKind::LengthAccuracy,
+        Axes::new(AccuracyScale::Phred),
+    );
+    hist.merge(&Histogram2D::new(
+        HistogramKind::LengthAccuracy,
+        Axes::new(AccuracyScale::Percent),
+    ));
+}
+
+#[test]
+#[should_panic]
+fn test_merge_different_range() {
+    let axes = Axes::new(AccuracyScale::Phred);
+    let mut hist = Histogram2D::new(HistogramKind::LengthAccuracy, axes);
+    let hifi = axes.with_ranges(None, Some(20.0), Some(50.0)).unwrap();
+    hist.merge(&Histogram2D::new(HistogramKind::LengthAccuracy, hifi));
+}
+
+#[test]
+fn test_downsample() {
+    let mut hist = Histogram2D::new(
+        HistogramKind::LengthAccuracy,
+        Axes::new(AccuracyScale::Percent),
+    );
+    for x in 0..100 {
+        for _ in 0..10 {
+            hist.add(x, 0);
+        }
+    }
+    let downsampled = hist.downsample(250);
+    assert_eq!(downsampled.total(), 250);
+    assert_eq!(
+        downsampled.iter().map(|(_bin, count)| count).sum::<f64>(),
+        250.0
+    );
+    assert!(downsampled
+        .iter()
+        .all(|((_x, y), count)| y == 0 && count <= 10.0));
+    assert_eq!(downsampled, hist.downsample(250));
+    assert_eq!(hist.downsample(5000), hist);
+}
+
+#[test]
+fn test_smooth() {
+    let mut hist = Histogram2D::new(
+        HistogramKind::LengthAccuracy,
+        Axes::new(AccuracyScale::Percent),
+    );
+    for _ in 0..100 {
+        hist.add(300, 300);
+    }
+    hist.smooth(2.0);
+    // the reads are spread symmetrically over the neighbouring bins
+    assert!(hist.get(300, 300) < 10.0);
+    assert_eq!(hist.get(298, 300), hist.get(302, 300));
+    assert!((hist.get(300, 298) - hist.get(298, 300)).abs() < 1e-12);
+    assert_eq!(hist.get(307, 300), 0.0);
+    assert!((hist.iter().map(|(_bin, count)| count).sum::<f64>() - 100.0).abs() < 1e-3);
+    assert_eq!(hist.total(), 100);
+}
+
+#[test]
+fn test_n50_and_median() {
+    let axes = Axes::new(AccuracyScale::Percent);
+    let mut hist = Histogram2D::new(HistogramKind::LengthAccuracy, axes);
+    // 10 reads of 100 bp at 95% and 1 read of 10 kb at 90%
+    for _ in 0..10 {
+        hist.add(axes.transform_length(100), axes.transform_accuracy(95.0));
+    }
+    hist.add(axes.transform_length(10000), axes.transform_accuracy(90.0));
+    let n50 = hist.length_n50().unwrap();
+    assert!((n50 / 10000.0 - 1.0).abs() < 0.02);
+    assert_eq!(
+        axes.transform_length(n50 as usize),
+        axes.transform_length(10000)
+    );
+    let median = hist.median_accuracy().unwrap();
+    assert!((median - 95.0).abs() < 0.05);
+    assert_eq!(
+        axes.transform_accuracy(median),
+        axes.transform_accuracy(95.0)
+    );
+    let calibration = Histogram2D::new(HistogramKind::Calibration, axes);
+    assert_eq!(calibration.length_n50(), None);
+    assert_eq!(
+        Histogram2D::new(HistogramKind::LengthAccuracy, axes).median_accuracy(),
+        None
+    );
+}
+
+#[test]
+fn test_edges() {
+    let hist = Histogram2D::new(
+        HistogramKind::LengthAccuracy,
+        Axes::new(AccuracyScale::Percent),
+    );
+    assert_eq!(hist.x_edges().len(), BINS + 1);
+    assert!((hist.x_edges()[300] - 1000.0).abs() < 0.1);
+    assert_eq!(hist.y_edges()[0], 100.0);
+    assert_eq!(hist.y_edges()[600], 70.0);
+}
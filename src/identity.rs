@@ -1,3 +1,5 @@
+use std::sync::OnceLock;
+
 use rust_htslib::bam::{
     self,
     record::{Aux, Cigar},
@@ -7,31 +9,71 @@ use rust_htslib::bam::{
 /// based on https://lh3.github.io/2018/11/25/on-the-definition-of-sequence-identity
 /// recent minimap2 version have that as the de tag
 /// if that is not present it is calculated from CIGAR and NM
-pub fn gap_compressed_identity(record: std::rc::Rc<rust_htslib::bam::Record>) -> f32 {
-    match get_de_tag(&record) {
+pub fn gap_compressed_identity(record: &bam::Record) -> f32 {
+    match get_de_tag(record) {
         Some(v) => v,
         None => {
-            let mut matches = 0;
-            let mut gap_size = 0;
-            let mut gap_count = 0;
-            for entry in record.cigar().iter() {
-                match entry {
-                    Cigar::Match(len) | Cigar::Equal(len) | Cigar::Diff(len) => {
-                        matches += *len;
-                    }
-                    Cigar::Del(len) | Cigar::Ins(len) => {
-                        gap_size += *len;
-                        gap_count += 1;
-                    }
-                    _ => (),
-                }
-            }
-            100.0 * (1.0 - ((get_nm_tag(&record) - gap_size + gap_count) as f32
+            let (matches, gap_size, gap_count) = cigar_counts(record);
+            100.0 * (1.0 - ((get_nm_tag(record) - gap_size + gap_count) as f32
                 / (matches + gap_count) as f32))
         }
     }
 }
 
+/// The differences and the columns of the gap-compressed alignment of a record,
+/// which can be summed over the alignments of a read that is split over several records
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct AlignmentDifferences {
+    pub differences: f64,
+    pub columns: f64,
+}
+
+impl AlignmentDifferences {
+    /// The differences of a record, from the de tag if present as for the identity of a record
+    pub fn of(record: &bam::Record) -> AlignmentDifferences {
+        let (matches, gap_size, gap_count) = cigar_counts(record);
+        let columns = f64::from(matches + gap_count);
+        let differences = match get_de_tag(record) {
+            Some(identity) => (1.0 - f64::from(identity) / 100.0) * columns,
+            None => f64::from(get_nm_tag(record) - gap_size + gap_count),
+        };
+        AlignmentDifferences {
+            differences,
+            columns,
+        }
+    }
+
+    pub fn add(&mut self, other: AlignmentDifferences) {
+        self.differences += other.differences;
+        self.columns += other.columns;
+    }
+
+    /// The gap-compressed identity of all the alignments together
+    pub fn identity(&self) -> f32 {
+        (100.0 * (1.0 - self.differences / self.columns)) as f32
+    }
+}
+
+// the aligned bases, and the length and number of the gaps
+fn cigar_counts(record: &bam::Record) -> (u32, u32, u32) {
+    let mut matches = 0;
+    let mut gap_size = 0;
+    let mut gap_count = 0;
+    for entry in record.cigar().iter() {
+        match entry {
+            Cigar::Match(len) | Cigar::Equal(len) | Cigar::Diff(len) => {
+                matches += *len;
+            }
+            Cigar::Del(len) | Cigar::Ins(len) => {
+                gap_size += *len;
+                gap_count += 1;
+            }
+            _ => (),
+        }
+    }
+    (matches, gap_size, gap_count)
+}
+
 fn get_nm_tag(record: &bam::Record) -> u32 {
     match record.aux(b"NM") {
         Ok(value) => match value {
@@ -57,41 +99,112 @@ fn get_de_tag(record: &bam::Record) -> Option<f32> {
     }
 }
 
-
-pub fn ubam_accuracy(record: std::rc::Rc<rust_htslib::bam::Record>) -> f32 {
-    // get the expected accuracy from the quality scores in the bam file
-    // for this, convert each quality score to the error probability
-    // and calculate the average error probability
-    let mut error_probabilities = Vec::new();
-    for quality in record.qual().iter() {
-        error_probabilities.push(10.0f32.powf(-(*quality as f32) / 10.0));
+/// Get the expected accuracy from the quality scores in the bam file
+/// for this, convert each quality score to the error probability
+/// and calculate the average error probability, accumulated in f64 to remain accurate for very long reads
+/// Returns None for reads without quality scores (stored as 0xFF) or without sequence
+pub fn ubam_accuracy(record: &bam::Record) -> Option<f32> {
+    let qualities = record.qual();
+    if qualities.is_empty() || qualities[0] == MISSING_QUALITY {
+        return None;
     }
-    100.0 * (1.0 - error_probabilities.iter().sum::<f32>() / error_probabilities.len() as f32)
+    let error_probabilities = phred_to_error_probabilities();
+    let error_sum: f64 = qualities
+        .iter()
+        .map(|quality| error_probabilities[*quality as usize])
+        .sum();
+    Some((100.0 * (1.0 - error_sum / qualities.len() as f64)) as f32)
 }
+
+const MISSING_QUALITY: u8 = 0xFF;
+
+static PHRED_TO_ERROR_PROBABILITY: OnceLock<[f64; 256]> = OnceLock::new();
+
+// lookup table for the error probability of each possible quality score
+fn phred_to_error_probabilities() -> &'static [f64; 256] {
+    PHRED_TO_ERROR_PROBABILITY.get_or_init(|| {
+        let mut table = [0.0; 256];
+        for (quality, probability) in table.iter_mut().enumerate() {
+            *probability = 10.0f64.powf(-(quality as f64) / 10.0);
+        }
+        table
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    fn create_record_with_qual(qual: &[u8]) -> std::rc::Rc<bam::Record> {
+    fn create_record_with_qual(qual: &[u8]) -> bam::Record {
         let mut record = bam::Record::new();
         // create a seq with the same length as the quality scores
         let seq= vec![b'A'; qual.len()];
         record.set(&[], None, &seq, qual);
-        std::rc::Rc::new(record)
+        record
     }
 
     #[test]
     fn test_ubam_accuracy() {
         let record = create_record_with_qual(&[30, 30, 30, 30, 30]);
-        let accuracy = ubam_accuracy(record);
+        let accuracy = ubam_accuracy(&record).unwrap();
         assert!((accuracy - 99.9).abs() < f32::EPSILON);
 
         let record = create_record_with_qual(&[20, 20, 20, 20, 20]);
-        let accuracy = ubam_accuracy(record);
+        let accuracy = ubam_accuracy(&record).unwrap();
         assert!((accuracy - 99.0).abs() < f32::EPSILON);
 
         let record = create_record_with_qual(&[10, 10, 10, 10, 10]);
-        let accuracy = ubam_accuracy(record);
+        let accuracy = ubam_accuracy(&record).unwrap();
         assert!((accuracy - 90.0).abs() < f32::EPSILON);
     }
+
+    fn create_aligned_record(cigar: Vec<Cigar>, nm: u32) -> bam::Record {
+        let cigar = bam::record::CigarString(cigar);
+        let seq = vec![b'A'; cigar.iter().map(|c| c.len() as usize).sum()];
+        let mut record = bam::Record::new();
+        record.set(b"read", Some(&cigar), &seq, &vec![30; seq.len()]);
+        record.push_aux(b"NM", Aux::U32(nm)).unwrap();
+        record
+    }
+
+    #[test]
+    fn test_alignment_differences() {
+        let first = create_aligned_record(vec![Cigar::Match(100)], 2);
+        let second =
+            create_aligned_record(vec![Cigar::Match(50), Cigar::Ins(2), Cigar::Match(48)], 4);
+        // a single alignment has the same identity as the record
+        let mut differences = AlignmentDifferences::of(&second);
+        assert!((differences.identity() - gap_compressed_identity(&second)).abs() < 1e-4);
+        // the gap of 2 bases counts as a single difference and column
+        differences.add(AlignmentDifferences::of(&first));
+        assert_eq!(differences.differences, 5.0);
+        assert_eq!(differences.columns, 199.0);
+    }
+
+    #[test]
+    fn test_ubam_accuracy_mixed_qualities() {
+        let record = create_record_with_qual(&[10, 20]);
+        let accuracy = ubam_accuracy(&record).unwrap();
+        assert!((accuracy - 94.5).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_ubam_accuracy_long_read() {
+        // a 1 Mb read, for which a sum in f32 drifts away from the expected value
+        let record = create_record_with_qual(&[30; 1_000_000]);
+        let accuracy = ubam_accuracy(&record).unwrap();
+        assert!((accuracy - 99.9).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_ubam_accuracy_missing_qualities() {
+        let record = create_record_with_qual(&[255, 255, 255]);
+        assert!(ubam_accuracy(&record).is_none());
+    }
+
+    #[test]
+    fn test_ubam_accuracy_empty_read() {
+        let record = create_record_with_qual(&[]);
+        assert!(ubam_accuracy(&record).is_none());
+    }
 }
@@ -0,0 +1,317 @@
+// Mapping of the counts in the bins of a histogram to the intensity of the pixels,
+// which is applied when rendering such that the saved counts are unchanged
+use clap::ValueEnum;
+
+use crate::histogram::Histogram2D;
+
+// the lowest intensity of a bin with reads on a non-linear scale, such that sparse bins stay visible
+pub(crate) const MIN_INTENSITY: f64 = 20.0;
+
+/// Transformation of the counts before these are scaled to the highest count
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum CountScale {
+    Linear,
+    Log2,
+    Log10,
+    Sqrt,
+    Asinh,
+}
+
+/// Normalization of each dataset before the datasets are plotted together,
+/// such that datasets with a different number of reads are comparable
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum SampleNorm {
+    /// the counts of reads
+    None,
+    /// the fraction of the reads of the dataset
+    Fraction,
+    /// the counts relative to the highest count of the dataset
+    Max,
+    /// the counts after randomly downsampling to the number of reads of the smallest dataset
+    Downsample,
+}
+
+impl SampleNorm {
+    pub fn apply(self, mut histograms: Vec<Histogram2D>) -> Vec<Histogram2D> {
+        match self {
+            SampleNorm::None => {}
+            SampleNorm::Fraction => {
+                for histogram in histograms.iter_mut().filter(|h| !h.is_empty()) {
+                    histogram.scale_counts(1.0 / histogram.total() as f64);
+                }
+            }
+            SampleNorm::Max => {
+                for histogram in histograms.iter_mut().filter(|h| !h.is_empty()) {
+                    histogram.scale_counts(1.0 / histogram.max());
+                }
+            }
+            SampleNorm::Downsample => {
+                let reads = histograms.iter().map(Histogram2D::total).min().unwrap_or(0);
+                histograms = histograms.iter().map(|h| h.downsample(reads)).collect();
+            }
+        }
+        histograms
+    }
+}
+
+/// How the counts are transformed to the intensity of the pixels
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Scaling {
+    pub scale: CountScale,
+    /// added to the counts before the transformation, which has to be positive for a log scale
+    pub pseudocount: f64,
+    /// count at the lowest intensity, 0 by default
+    pub vmin: Option<f64>,
+    /// count at which the intensity saturates, the highest count by default
+    pub vmax: Option<f64>,
+    /// saturate the intensity at this quantile of the counts of the bins with reads instead
+    pub vmax_quantile: Option<f64>,
+    /// normalization of each dataset, before the limits are determined
+    pub sample_norm: SampleNorm,
+    /// standard deviation in bins of the Gaussian kernel to smooth the counts with
+    pub smooth: Option<f64>,
+}
+
+impl Default for Scaling {
+    fn default() -> Scaling {
+        Scaling {
+            scale: CountScale::Linear,
+            pseudocount: 1.0,
+            vmin: None,
+            vmax: None,
+            vmax_quantile: None,
+            sample_norm: SampleNorm::None,
+            smooth: None,
+        }
+    }
+}
+
+impl Scaling {
+    /// The transformed count, which is 0 for an empty bin
+    pub fn transform(&self, count: f64) -> f64 {
+        let f = |x: f64| match self.scale {
+            CountScale::Linear => x,
+            CountScale::Log2 => x.log2(),
+            CountScale::Log10 => x.log10(),
+            CountScale::Sqrt => x.sqrt(),
+            CountScale::Asinh => x.asinh(),
+        };
+        f(count + self.pseudocount) - f(self.pseudocount)
+    }
+
+    /// The counts at the lowest and the highest intensity of the histograms plotted together,
+    /// such that a single bin with many reads does not wash out the rest of the plot
+    pub fn limits(&self, histograms: &[Histogram2D]) -> (f64, f64) {
+        self.limits_of_counts(
+            histograms
+                .iter()
+                .flat_map(|h| h.iter().map(|(_bin, count)| count)),
+        )
+    }
+
+    /// The counts at the lowest and the highest intensity of any counts, e.g. of the reads
+    /// of the channels of a flow cell, with the highest count at --vmax-quantile of the counts
+    pub fn limits_of_counts<I: IntoIterator<Item = f64>>(&self, counts: I) -> (f64, f64) {
+        let vmin = self.vmin.unwrap_or(0.0);
+        let vmax = match (self.vmax, self.vmax_quantile) {
+            (Some(vmax), _) => vmax,
+            (None, Some(quantile)) => {
+                let mut counts = counts.into_iter().collect::<Vec<f64>>();
+                counts.sort_by(f64::total_cmp);
+                // the nearest rank, which is the highest count for a quantile of 1
+                let rank = (quantile * counts.len() as f64).ceil() as usize;
+                counts.get(rank.saturating_sub(1)).copied().unwrap_or(0.0)
+            }
+            (None, None) => counts.into_iter().fold(0.0, f64::max),
+        };
+        (vmin, vmax)
+    }
+
+    /// The position (0-1) of a count between the limits, which saturates above the highest limit
+    pub fn relative(&self, count: f64, (vmin, vmax): (f64, f64)) -> f64 {
+        let range = self.transform(vmax) - self.transform(vmin);
+        if range > 0.0 {
+            ((self.transform(count) - self.transform(vmin)) / range).clamp(0.0, 1.0)
+        } else {
+            // all bins with more reads than the lowest limit are saturated
+            f64::from(count > vmin)
+        }
+    }
+
+    /// The intensity (0-255) of a bin between the counts at the limits, with bins with reads
+    /// at least at MIN_INTENSITY on a non-linear scale, while the linear scale is unchanged
+    /// Bins with at most the count of --vmin are not drawn
+    pub fn intensity(&self, count: f64, limits: (f64, f64)) -> f64 {
+        if count <= 0.0 || self.vmin.is_some_and(|vmin| count <= vmin) {
+            return 0.0;
+        }
+        let intensity = self.relative(count, limits) * 255.0;
+        match self.scale {
+            CountScale::Linear => intensity,
+            _ => intensity.max(MIN_INTENSITY),
+        }
+    }
+
+    /// The histograms after the normalization of each dataset and the smoothing of the counts
+    pub fn prepare(&self, histograms: Vec<Histogram2D>) -> Vec<Histogram2D> {
+        let mut histograms = self.sample_norm.apply(histograms);
+        if let Some(sigma) = self.smooth {
+            histograms.iter_mut().for_each(|h| h.smooth(sigma));
+        }
+        histograms
+    }
+}
+
+/// Parse the --pseudocount and --smooth arguments, which have to be positive
+pub fn parse_positive(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(number) if number > 0.0 && number.is_finite() => Ok(number),
+        _ => Err(format!("Invalid value {value}, expected a positive number")),
+    }
+}
+
+/// Parse the --vmax-quantile argument, which has to be between 0 and 1
+pub fn parse_quantile(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(quantile) if quantile > 0.0 && quantile <= 1.0 => Ok(quantile),
+        _ => Err(format!(
+            "Invalid quantile {value}, expected a number in (0, 1]"
+        )),
+    }
+}
+
+#[test]
+fn test_transform() {
+    let log2 = Scaling {
+        scale: CountScale::Log2,
+        ..Default::default()
+    };
+    assert_eq!(log2.transform(0.0), 0.0);
+    assert_eq!(log2.transform(1.0), 1.0);
+    assert_eq!(log2.transform(7.0), 3.0);
+    assert_eq!(Scaling::default().transform(5.0), 5.0);
+    let sqrt = Scaling {
+        scale: CountScale::Sqrt,
+        pseudocount: 0.0,
+        ..Default::default()
+    };
+    assert_eq!(sqrt.transform(16.0), 4.0);
+}
+
+#[test]
+fn test_intensity() {
+    let log10 = Scaling {
+        scale: CountScale::Log10,
+        ..Default::default()
+    };
+    assert_eq!(log10.intensity(0.0, (0.0, 999.0)), 0.0);
+    assert_eq!(log10.intensity(999.0, (0.0, 999.0)), 255.0);
+    assert!((log10.intensity(9.0, (0.0, 999.0)) - 85.0).abs() < 1e-9);
+    // a single read is not lost next to a bin with many reads, except on the linear scale
+    assert_eq!(log10.intensity(1.0, (0.0, 1e9)), MIN_INTENSITY);
+    assert!(Scaling::default().intensity(1.0, (0.0, 1e6)) < 1.0);
+    // the intensity saturates above the highest limit
+    let linear = Scaling::default();
+    assert_eq!(linear.intensity(50.0, (10.0, 20.0)), 255.0);
+    assert_eq!(linear.intensity(15.0, (10.0, 20.0)), 127.5);
+    assert_eq!(linear.intensity(5.0, (10.0, 10.0)), 0.0);
+    assert_eq!(linear.intensity(15.0, (10.0, 10.0)), 255.0);
+    // bins at or below --vmin are clipped, and the bins above it stay visible
+    let vmin = Scaling {
+        vmin: Some(10.0),
+        ..log10
+    };
+    assert_eq!(vmin.intensity(5.0, (10.0, 1000.0)), 0.0);
+    assert_eq!(vmin.intensity(10.0, (10.0, 1000.0)), 0.0);
+    assert_eq!(vmin.intensity(11.0, (10.0, 1000.0)), MIN_INTENSITY);
+}
+
+#[test]
+fn test_limits() {
+    let mut histogram = Histogram2D::new(
+        crate::histogram::HistogramKind::LengthAccuracy,
+        crate::transform::Axes::new(crate::transform::AccuracyScale::Percent),
+    );
+    // a hot bin with 1000 reads and 99 bins with a single read
+    for _ in 0..1000 {
+        histogram.add(0, 0);
+    }
+    for x in 1..100 {
+        histogram.add(x, 1);
+    }
+    let histograms = [histogram];
+    assert_eq!(Scaling::default().limits(&histograms), (0.0, 1000.0));
+    let quantile = Scaling {
+        vmax_quantile: Some(0.99),
+        ..Default::default()
+    };
+    assert_eq!(quantile.limits(&histograms), (0.0, 1.0));
+    let explicit = Scaling {
+        vmin: Some(2.0),
+        vmax: Some(500.0),
+        vmax_quantile: Some(0.99),
+        ..Default::default()
+    };
+    assert_eq!(explicit.limits(&histograms), (2.0, 500.0));
+}
+
+#[test]
+fn test_sample_norm() {
+    let axes = crate::transform::Axes::new(crate::transform::AccuracyScale::Percent);
+    let kind = crate::histogram::HistogramKind::LengthAccuracy;
+    let mut deep = Histogram2D::new(kind, axes);
+    for _ in 0..40 {
+        deep.add(1, 1);
+    }
+    for _ in 0..10 {
+        deep.add(2, 2);
+    }
+    let mut shallow = Histogram2D::new(kind, axes);
+    for _ in 0..5 {
+        shallow.add(1, 1);
+    }
+    let histograms = vec![deep, shallow];
+    let fraction = SampleNorm::Fraction.apply(histograms.clone());
+    assert_eq!(fraction[0].get(1, 1), 0.8);
+    assert_eq!(fraction[1].get(1, 1), 1.0);
+    let max = SampleNorm::Max.apply(histograms.clone());
+    assert_eq!(max[0].get(2, 2), 0.25);
+    assert_eq!(max[1].get(1, 1), 1.0);
+    let downsampled = SampleNorm::Downsample.apply(histograms.clone());
+    assert_eq!(downsampled[0].total(), 5);
+    assert_eq!(downsampled[1], histograms[1]);
+    assert_eq!(SampleNorm::None.apply(histograms.clone()), histograms);
+}
+
+#[test]
+fn test_normalized_and_smoothed() {
+    let axes = crate::transform::Axes::new(crate::transform::AccuracyScale::Percent);
+    let mut histogram = Histogram2D::new(crate::histogram::HistogramKind::LengthAccuracy, axes);
+    for x in 0..50 {
+        histogram.add(100 + 7 * x, 300);
+    }
+    let smoothed = Scaling {
+        smooth: Some(2.0),
+        ..Default::default()
+    };
+    let counts = smoothed.prepare(vec![histogram.clone()]);
+    // the fractions are smoothed to the same bins as the counts of the reads
+    let fraction = Scaling {
+        sample_norm: SampleNorm::Fraction,
+        ..smoothed
+    };
+    let fractions = fraction.prepare(vec![histogram]);
+    assert_eq!(fractions[0].iter().count(), counts[0].iter().count());
+    assert!((fractions[0].get(100, 300) * 50.0 - counts[0].get(100, 300)).abs() < 1e-9);
+}
+
+#[test]
+fn test_parse_arguments() {
+    assert_eq!(parse_positive("0.5"), Ok(0.5));
+    assert!(parse_positive("0").is_err());
+    assert!(parse_positive("-1").is_err());
+    assert!(parse_positive("one").is_err());
+    assert_eq!(parse_quantile("0.99"), Ok(0.99));
+    assert!(parse_quantile("0").is_err());
+    assert!(parse_quantile("1.5").is_err());
+}
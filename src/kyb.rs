@@ -0,0 +1,625 @@
+// kyber files (.kyb) hold the histograms of one or more datasets, with a description
+// of how these were made, so that these can be merged or plotted later without reading
+// the bam files again. The layout is, in little endian with strings as their length (u32)
+// followed by utf-8:
+//   magic "KYB", format version (u8), version of kyber (string), metric (u8),
+//   number of filters (u32) and filters (strings), number of inputs (u32) and inputs (strings),
+//   whether the datasets are groups of reads (u8), number of datasets (u32)
+//   for each dataset: name (string), kind (u8), scale (u8), maximal length (u64),
+//   minimal and maximal accuracy (2 x f32), maximal time in hours (f32), total number of reads (u64),
+//   number of bins with a count (u32) and for each of these bins: x (u16), y (u16), count (f64)
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use log::info;
+
+use crate::histogram::{Histogram2D, HistogramKind};
+use crate::transform::{AccuracyScale, Axes, PLOT_SIZE};
+
+const MAGIC: &[u8; 3] = b"KYB";
+/// Version of the layout of kyber files, which is increased with every change
+pub const FORMAT_VERSION: u8 = 1;
+
+/// Histogram of a dataset, with the name of the input file or group of reads
+pub type Dataset = (String, Histogram2D);
+
+/// Definition of the accuracy of reads on the y-axis of the histograms
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Metric {
+    /// from the alignment to the reference
+    GapCompressedIdentity,
+    /// from the base qualities of unaligned reads
+    PredictedAccuracy,
+}
+
+impl fmt::Display for Metric {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Metric::GapCompressedIdentity => write!(f, "gap-compressed identity"),
+            Metric::PredictedAccuracy => write!(f, "accuracy predicted from base qualities"),
+        }
+    }
+}
+
+/// How the histograms were made
+#[derive(Clone, Debug, PartialEq)]
+pub struct Metadata {
+    /// version of kyber
+    pub version: String,
+    pub metric: Metric,
+    /// filters on the reads, including subsampling
+    pub filters: Vec<String>,
+    /// files from which the reads were taken
+    pub inputs: Vec<String>,
+    /// whether the datasets are groups of reads from --split-by, rather than input files
+    pub grouped: bool,
+}
+
+impl Metadata {
+    pub fn new(metric: Metric, filters: Vec<String>, inputs: Vec<String>) -> Metadata {
+        Metadata {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            metric,
+            filters,
+            inputs,
+            grouped: false,
+        }
+    }
+}
+
+/// The content of a kyber file
+#[derive(Clone, Debug, PartialEq)]
+pub struct KybFile {
+    pub metadata: Metadata,
+    pub datasets: Vec<Dataset>,
+}
+
+/// Check if the file name has the .kyb extension of kyber files
+pub fn is_kyb(path: &str) -> bool {
+    path.ends_with(".kyb")
+}
+
+/// Check if the file name has the .tsv extension, to save histograms as a table
+pub fn is_tsv(path: &str) -> bool {
+    path.ends_with(".tsv")
+}
+
+/// Save the histograms of the datasets to a kyber file
+pub fn write_kyb(path: &str, kyb: &KybFile) {
+    let file = File::create(path).unwrap_or_else(|e| panic!("Error creating {path}: {e}"));
+    let mut writer = BufWriter::new(file);
+    write_kyb_file(&mut writer, kyb)
+        .and_then(|_| writer.flush())
+        .unwrap_or_else(|e| panic!("Error writing {path}: {e}"));
+}
+
+/// Read the histograms of the datasets from a kyber file
+pub fn read_kyb(path: &str) -> KybFile {
+    let file = File::open(path).unwrap_or_else(|e| panic!("Error opening {path}: {e}"));
+    read_kyb_file(&mut BufReader::new(file))
+        .unwrap_or_else(|e| panic!("\n\nERROR: could not read kyber file {path}: {e}"))
+}
+
+/// Save the histograms as a table with the range of each bin, which is readable
+/// by humans and other tools, with the metadata in comment lines
+pub fn write_tsv(path: &str, kyb: &KybFile) {
+    let file = File::create(path).unwrap_or_else(|e| panic!("Error creating {path}: {e}"));
+    let mut writer = BufWriter::new(file);
+    write_tsv_file(&mut writer, kyb)
+        .and_then(|_| writer.flush())
+        .unwrap_or_else(|e| panic!("Error writing {path}: {e}"));
+}
+
+/// Sum the histograms of multiple kyber files and save the result to a new kyber file
+/// Files with the single dataset of an input file, e.g. from chunks of a flowcell or from
+/// an earlier merge, are summed to a single dataset named after the output file. Otherwise
+/// datasets are summed by name, e.g. for the read groups from --split-by, in which a group
+/// can be absent from some of the files
+pub fn merge_kyb(inputs: &[String], output: &str) {
+    let files: Vec<KybFile> = inputs.iter().map(|f| read_kyb(f)).collect();
+    let metadata = merge_metadata(inputs, files.iter().map(|f| &f.metadata));
+    check_axes(inputs, &files);
+    write_kyb(output, &merge_files(files, metadata, output));
+}
+
+// the datasets of the files summed by name, or to a single dataset for files of a single input
+fn merge_files(files: Vec<KybFile>, metadata: Metadata, output: &str) -> KybFile {
+    let single_input = files
+        .iter()
+        .all(|f| !f.metadata.grouped && f.datasets.len() == 1);
+    let datasets = if single_input {
+        let name = Path::new(output)
+            .file_stem()
+            .map_or_else(|| output.to_string(), |s| s.to_string_lossy().to_string());
+        merge_datasets(
+            files
+                .into_iter()
+                .flat_map(|f| f.datasets)
+                .map(|(_name, h)| (name.clone(), h)),
+        )
+    } else {
+        merge_datasets(files.into_iter().flat_map(|f| f.datasets))
+    };
+    for (name, histogram) in &datasets {
+        info!("Merged {} reads of {name}", histogram.total());
+    }
+    KybFile { metadata, datasets }
+}
+
+/// Check that histograms can be summed, as the reads were selected and measured in the same way,
+/// and combine the inputs of the files
+pub fn merge_metadata<'a, I>(paths: &[String], metadata: I) -> Metadata
+where
+    I: IntoIterator<Item = &'a Metadata>,
+{
+    let mut metadata = metadata.into_iter().zip(paths);
+    let (first, first_path) = metadata.next().expect("ERROR no kyber files to merge");
+    let mut inputs = first.inputs.clone();
+    let mut grouped = first.grouped;
+    for (other, path) in metadata {
+        // the reads may be measured or binned differently by another version
+        if other.version != first.version {
+            panic!(
+                "\n\nERROR: cannot merge the histograms of kyber {} in {first_path} with those of kyber {} in {path}!",
+                first.version, other.version
+            );
+        }
+        if other.metric != first.metric {
+            panic!(
+                "\n\nERROR: cannot merge the {} in {first_path} with the {} in {path}!",
+                first.metric, other.metric
+            );
+        }
+        if other.filters != first.filters {
+            panic!(
+                "\n\nERROR: cannot merge reads filtered by {:?} in {first_path} with reads filtered by {:?} in {path}!",
+                first.filters, other.filters
+            );
+        }
+        inputs.extend(other.inputs.iter().cloned());
+        grouped |= other.grouped;
+    }
+    let mut merged = Metadata::new(first.metric, first.filters.clone(), inputs);
+    merged.grouped = grouped;
+    merged
+}
+
+/// Check that the histograms of the files have the same axes, such that these can be summed
+/// or plotted together
+pub fn check_axes(paths: &[String], files: &[KybFile]) {
+    let mut histograms = files
+        .iter()
+        .zip(paths)
+        .flat_map(|(f, path)| f.datasets.iter().map(move |(_, h)| (h, path)));
+    let Some((first, first_path)) = histograms.next() else {
+        return;
+    };
+    for (other, path) in histograms {
+        if let Some((parameter, first, other)) = axes_difference(first, other) {
+            panic!(
+                "\n\nERROR: cannot merge histograms with {parameter} of {first} in {first_path} and {other} in {path}!"
+            );
+        }
+    }
+}
+
+// the first parameter of the axes in which two histograms differ, with its value for each
+fn axes_difference(
+    first: &Histogram2D,
+    other: &Histogram2D,
+) -> Option<(&'static str, String, String)> {
+    let (a, b) = (first.axes(), other.axes());
+    if first.kind() != other.kind() {
+        Some((
+            "an x-axis",
+            x_variable(first.kind()),
+            x_variable(other.kind()),
+        ))
+    } else if a.scale != b.scale {
+        Some((
+            "an accuracy scale",
+            format!("{:?}", a.scale).to_lowercase(),
+            format!("{:?}", b.scale).to_lowercase(),
+        ))
+    } else if a.max_length != b.max_length {
+        Some((
+            "a longest read length",
+            format!("{} bp", a.max_length),
+            format!("{} bp", b.max_length),
+        ))
+    } else if a.min_accuracy != b.min_accuracy {
+        Some((
+            "a lowest accuracy",
+            accuracy(a.min_accuracy, a.scale),
+            accuracy(b.min_accuracy, b.scale),
+        ))
+    } else if a.max_accuracy != b.max_accuracy {
+        Some((
+            "a highest accuracy",
+            accuracy(a.max_accuracy, a.scale),
+            accuracy(b.max_accuracy, b.scale),
+        ))
+    } else if a.max_time != b.max_time {
+        Some((
+            "a latest time",
+            format!("{} h", a.max_time),
+            format!("{} h", b.max_time),
+        ))
+    } else {
+        None
+    }
+}
+
+// the variable on the x-axis of a kind of histogram
+fn x_variable(kind: HistogramKind) -> String {
+    match kind {
+        HistogramKind::LengthAccuracy => String::from("read length"),
+        HistogramKind::Calibration => String::from("predicted accuracy"),
+        HistogramKind::TimeAccuracy => String::from("time into the run"),
+    }
+}
+
+// an accuracy in percent or as a phred score
+fn accuracy(value: f32, scale: AccuracyScale) -> String {
+    match scale {
+        AccuracyScale::Percent => format!("{value}%"),
+        AccuracyScale::Phred => format!("Q{value}"),
+    }
+}
+
+// sum the histograms with the same name, keeping the order in which names first appear
+fn merge_datasets<I>(datasets: I) -> Vec<Dataset>
+where
+    I: IntoIterator<Item = Dataset>,
+{
+    let mut merged: Vec<Dataset> = vec![];
+    for (name, histogram) in datasets {
+        match merged.iter_mut().find(|(n, _)| *n == name) {
+            Some((_, total)) => total.merge(&histogram),
+            None => merged.push((name, histogram)),
+        }
+    }
+    merged
+}
+
+fn write_kyb_file<W: Write>(writer: &mut W, kyb: &KybFile) -> io::Result<()> {
+    writer.write_all(MAGIC)?;
+    writer.write_all(&[FORMAT_VERSION])?;
+    let metadata = &kyb.metadata;
+    write_string(writer, &metadata.version)?;
+    let metric: u8 = match metadata.metric {
+        Metric::GapCompressedIdentity => 0,
+        Metric::PredictedAccuracy => 1,
+    };
+    writer.write_all(&[metric])?;
+    write_strings(writer, &metadata.filters)?;
+    write_strings(writer, &metadata.inputs)?;
+    writer.write_all(&[u8::from(metadata.grouped)])?;
+    writer.write_all(&(kyb.datasets.len() as u32).to_le_bytes())?;
+    for (name, histogram) in &kyb.datasets {
+        write_string(writer, name)?;
+        let kind: u8 = match histogram.kind() {
+            HistogramKind::LengthAccuracy => 0,
+            HistogramKind::Calibration => 1,
+            HistogramKind::TimeAccuracy => 2,
+        };
+        let scale: u8 = match histogram.scale() {
+            AccuracyScale::Percent => 0,
+            AccuracyScale::Phred => 1,
+        };
+        writer.write_all(&[kind, scale])?;
+        let axes = histogram.axes();
+        writer.write_all(&(axes.max_length as u64).to_le_bytes())?;
+        writer.write_all(&axes.min_accuracy.to_le_bytes())?;
+        writer.write_all(&axes.max_accuracy.to_le_bytes())?;
+        writer.write_all(&axes.max_time.to_le_bytes())?;
+        writer.write_all(&histogram.total().to_le_bytes())?;
+        writer.write_all(&(histogram.iter().count() as u32).to_le_bytes())?;
+        for ((x, y), count) in histogram.iter() {
+            writer.write_all(&(x as u16).to_le_bytes())?;
+            writer.write_all(&(y as u16).to_le_bytes())?;
+            writer.write_all(&count.to_le_bytes())?;
+        }
+    }
+    Ok(())
+}
+
+fn read_kyb_file<R: Read>(reader: &mut R) -> io::Result<KybFile> {
+    if read_array::<3, _>(reader)? != *MAGIC {
+        return Err(invalid_data("not a kyber file"));
+    }
+    let [format_version] = read_array(reader)?;
+    if format_version != FORMAT_VERSION {
+        return Err(invalid_data(&format!(
+            "format version {format_version} is not supported by kyber {}, which reads version {FORMAT_VERSION}",
+            env!("CARGO_PKG_VERSION")
+        )));
+    }
+    let version = read_string(reader)?;
+    let metric = match read_array(reader)? {
+        [0] => Metric::GapCompressedIdentity,
+        [1] => Metric::PredictedAccuracy,
+        _ => return Err(invalid_data("unknown metric")),
+    };
+    let filters = read_strings(reader)?;
+    let inputs = read_strings(reader)?;
+    let grouped = match read_array(reader)? {
+        [0] => false,
+        [1] => true,
+        _ => return Err(invalid_data("unknown kind of datasets")),
+    };
+    let number_of_datasets = u32::from_le_bytes(read_array(reader)?);
+    let mut datasets = vec![];
+    for _ in 0..number_of_datasets {
+        let name = read_string(reader)?;
+        let [kind, scale] = read_array(reader)?;
+        let kind = match kind {
+            0 => HistogramKind::LengthAccuracy,
+            1 => HistogramKind::Calibration,
+            2 => HistogramKind::TimeAccuracy,
+            _ => return Err(invalid_data("unknown kind of histogram")),
+        };
+        let scale = match scale {
+            0 => AccuracyScale::Percent,
+            1 => AccuracyScale::Phred,
+            _ => return Err(invalid_data("unknown accuracy scale")),
+        };
+        let max_length = u64::from_le_bytes(read_array(reader)?) as usize;
+        let min_accuracy = f32::from_le_bytes(read_array(reader)?);
+        let max_accuracy = f32::from_le_bytes(read_array(reader)?);
+        let max_time = f32::from_le_bytes(read_array(reader)?);
+        let axes = Axes::new(scale)
+            .with_ranges(Some(max_length), Some(min_accuracy), Some(max_accuracy))
+            .and_then(|axes| axes.with_max_time(Some(max_time)))
+            .map_err(|e| invalid_data(&format!("the axes of {name} are invalid: {e}")))?;
+        let total = u64::from_le_bytes(read_array(reader)?);
+        let number_of_bins = u32::from_le_bytes(read_array(reader)?);
+        let mut bins = vec![];
+        for _ in 0..number_of_bins {
+            let x = u16::from_le_bytes(read_array(reader)?) as usize;
+            let y = u16::from_le_bytes(read_array(reader)?) as usize;
+            let count = f64::from_le_bytes(read_array(reader)?);
+            if x > PLOT_SIZE || y > PLOT_SIZE {
+                return Err(invalid_data("bin outside of the plot"));
+            }
+            bins.push(((x, y), count));
+        }
+        datasets.push((name, Histogram2D::from_bins(kind, axes, total, bins)));
+    }
+    Ok(KybFile {
+        metadata: Metadata {
+            version,
+            metric,
+            filters,
+            inputs,
+            grouped,
+        },
+        datasets,
+    })
+}
+
+fn write_tsv_file<W: Write>(writer: &mut W, kyb: &KybFile) -> io::Result<()> {
+    let metadata = &kyb.metadata;
+    writeln!(writer, "# kyber {}", metadata.version)?;
+    writeln!(writer, "# metric: {}", metadata.metric)?;
+    writeln!(writer, "# filters: {}", metadata.filters.join("; "))?;
+    writeln!(writer, "# inputs: {}", metadata.inputs.join(", "))?;
+    for (name, histogram) in &kyb.datasets {
+        let axes = histogram.axes();
+        let x_axis = match histogram.kind() {
+            HistogramKind::LengthAccuracy => format!("read length up to {} bp", axes.max_length),
+            HistogramKind::Calibration => x_variable(HistogramKind::Calibration),
+            HistogramKind::TimeAccuracy => format!("time into the run up to {} h", axes.max_time),
+        };
+        let (from, to) = (
+            accuracy(axes.min_accuracy, axes.scale),
+            accuracy(axes.max_accuracy, axes.scale),
+        );
+        writeln!(
+            writer,
+            "# {name}: {} reads, {x_axis} against accuracy from {from} to {to}",
+            histogram.total(),
+        )?;
+    }
+    writeln!(writer, "dataset\tx_from\tx_to\ty_from\ty_to\tcount")?;
+    for (name, histogram) in &kyb.datasets {
+        let (x_edges, y_edges) = (histogram.x_edges(), histogram.y_edges());
+        for ((x, y), count) in histogram.iter() {
+            writeln!(
+                writer,
+                "{name}\t{}\t{}\t{}\t{}\t{count}",
+                x_edges[x],
+                x_edges[x + 1],
+                y_edges[y],
+                y_edges[y + 1]
+            )?;
+        }
+    }
+    Ok(())
+}
+
+fn write_string<W: Write>(writer: &mut W, string: &str) -> io::Result<()> {
+    writer.write_all(&(string.len() as u32).to_le_bytes())?;
+    writer.write_all(string.as_bytes())
+}
+
+fn write_strings<W: Write>(writer: &mut W, strings: &[String]) -> io::Result<()> {
+    writer.write_all(&(strings.len() as u32).to_le_bytes())?;
+    strings.iter().try_for_each(|s| write_string(writer, s))
+}
+
+// the string is read up to the end of the file, such that the length of a string
+// in a corrupt file can not cause a huge allocation
+fn read_string<R: Read>(reader: &mut R) -> io::Result<String> {
+    let length = u32::from_le_bytes(read_array(reader)?);
+    let mut string = vec![];
+    reader.take(u64::from(length)).read_to_end(&mut string)?;
+    if string.len() != length as usize {
+        return Err(invalid_data("string longer than the rest of the file"));
+    }
+    String::from_utf8(string).map_err(|_| invalid_data("invalid string"))
+}
+
+fn read_strings<R: Read>(reader: &mut R) -> io::Result<Vec<String>> {
+    let number = u32::from_le_bytes(read_array(reader)?);
+    (0..number).map(|_| read_string(reader)).collect()
+}
+
+fn read_array<const N: usize, R: Read>(reader: &mut R) -> io::Result<[u8; N]> {
+    let mut buffer = [0; N];
+    reader.read_exact(&mut buffer)?;
+    Ok(buffer)
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+fn example_file() -> KybFile {
+    let axes = Axes::new(AccuracyScale::Phred)
+        .with_ranges(None, Some(10.0), Some(50.0))
+        .unwrap();
+    let mut histogram = Histogram2D::new(HistogramKind::LengthAccuracy, axes);
+    histogram.add(300, 20);
+    histogram.add(300, 20);
+    histogram.add(600, 0);
+    KybFile {
+        metadata: Metadata::new(
+            Metric::GapCompressedIdentity,
+            vec![String::from("mapped and not secondary")],
+            vec![String::from("reads.bam")],
+        ),
+        datasets: vec![(String::from("sample"), histogram)],
+    }
+}
+
+#[test]
+fn test_write_and_read() {
+    let kyb = example_file();
+    let mut buffer = vec![];
+    write_kyb_file(&mut buffer, &kyb).unwrap();
+    assert_eq!(read_kyb_file(&mut buffer.as_slice()).unwrap(), kyb);
+}
+
+#[test]
+fn test_read_invalid_file() {
+    assert!(read_kyb_file(&mut b"KYC\x01".as_slice()).is_err());
+    assert!(read_kyb_file(&mut b"KYB\x01\x01\0\0\0".as_slice()).is_err());
+    // files of the future or of before the format was versioned are rejected
+    let error = read_kyb_file(&mut b"KYB\x02".as_slice()).unwrap_err();
+    assert!(error.to_string().contains("format version 2"));
+    // a string that is longer than the file
+    let error = read_kyb_file(&mut b"KYB\x01\xff\xff\xff\xffkyber".as_slice()).unwrap_err();
+    assert!(error.to_string().contains("longer than the rest"));
+    assert!(read_kyb_file(&mut b"KYB\0\x01\0\0\0".as_slice()).is_err());
+}
+
+#[test]
+fn test_read_invalid_axes() {
+    let mut buffer = vec![];
+    write_kyb_file(&mut buffer, &example_file()).unwrap();
+    // change the minimal accuracy, which is followed by the maximal accuracy,
+    // the maximal time, the total, the number of bins and two bins
+    let position = buffer.len() - 4 - 4 - 4 - 8 - 4 - 2 * 12;
+    buffer[position..position + 4].copy_from_slice(&60.0f32.to_le_bytes());
+    let error = read_kyb_file(&mut buffer.as_slice()).unwrap_err();
+    assert!(error.to_string().contains("axes of sample are invalid"));
+}
+
+#[test]
+fn test_write_tsv() {
+    let mut buffer = vec![];
+    write_tsv_file(&mut buffer, &example_file()).unwrap();
+    let tsv = String::from_utf8(buffer).unwrap();
+    assert!(tsv.starts_with(&format!("# kyber {}\n", env!("CARGO_PKG_VERSION"))));
+    assert!(tsv.contains(
+        "# sample: 3 reads, read length up to 1000000 bp against accuracy from Q10 to Q50\n"
+    ));
+    assert!(tsv.contains("\nsample\t1000\t"));
+    assert_eq!(tsv.lines().count(), 8);
+}
+
+#[test]
+#[should_panic]
+fn test_merge_different_metric() {
+    let first = example_file().metadata;
+    let mut second = first.clone();
+    second.metric = Metric::PredictedAccuracy;
+    merge_metadata(&[String::from("a.kyb"), String::from("b.kyb")], [&first, &second]);
+}
+
+#[test]
+#[should_panic(expected = "kyber 0.1.0 in b.kyb")]
+fn test_merge_different_version() {
+    let first = example_file().metadata;
+    let mut second = first.clone();
+    second.version = String::from("0.1.0");
+    merge_metadata(&[String::from("a.kyb"), String::from("b.kyb")], [&first, &second]);
+}
+
+#[test]
+#[should_panic(expected = "a longest read length of 1000000 bp in a.kyb and 50000 bp in b.kyb")]
+fn test_merge_different_axes() {
+    let first = example_file();
+    let mut second = example_file();
+    let mut axes = first.datasets[0].1.axes();
+    axes.max_length = 50000;
+    second.datasets[0].1 = Histogram2D::new(HistogramKind::LengthAccuracy, axes);
+    check_axes(&[String::from("a.kyb"), String::from("b.kyb")], &[first, second]);
+}
+
+#[test]
+fn test_merge_datasets() {
+    let axes = Axes::new(AccuracyScale::Percent);
+    let mut histogram = Histogram2D::new(HistogramKind::LengthAccuracy, axes);
+    histogram.add(100, 100);
+    let merged = merge_datasets(vec![
+        (String::from("rg1"), histogram.clone()),
+        (String::from("rg2"), histogram.clone()),
+        (String::from("rg1"), histogram),
+    ]);
+    assert_eq!(merged.len(), 2);
+    assert_eq!(merged[0].0, "rg1");
+    assert_eq!(merged[0].1.get(100, 100), 2.0);
+    assert_eq!(merged[1].1.total(), 1);
+}
+
+#[test]
+fn test_merge_files() {
+    let chunk = |input: &str, name: &str, grouped: bool| {
+        let mut file = example_file();
+        file.metadata.inputs = vec![String::from(input)];
+        file.metadata.grouped = grouped;
+        file.datasets[0].0 = String::from(name);
+        file
+    };
+    let metadata = example_file().metadata;
+    // chunks of a flowcell are summed to a dataset named after the output
+    let chunks = vec![
+        chunk("a.bam", "a.bam", false),
+        chunk("b.bam", "b.bam", false),
+    ];
+    let total = merge_files(chunks, metadata.clone(), "total.kyb");
+    assert_eq!(total.datasets.len(), 1);
+    assert_eq!(total.datasets[0].0, "total");
+    assert_eq!(total.datasets[0].1.total(), 6);
+    // as are earlier merged files, with a name that is not one of their inputs
+    let more = vec![total.clone(), chunk("c.bam", "c.bam", false)];
+    let merged = merge_files(more, metadata.clone(), "all.kyb");
+    assert_eq!(merged.datasets.len(), 1);
+    assert_eq!(merged.datasets[0].0, "all");
+    assert_eq!(merged.datasets[0].1.total(), 9);
+    let merged = merge_files(vec![total.clone(), total], metadata.clone(), "twice.kyb");
+    assert_eq!(merged.datasets[0].1.total(), 12);
+    // files from --split-by that each hold a single group are not summed together
+    let groups = vec![chunk("a.bam", "rg1", true), chunk("b.bam", "rg2", true)];
+    let merged = merge_files(groups, metadata, "groups.kyb");
+    assert_eq!(merged.datasets.len(), 2);
+    assert_eq!(merged.datasets[0].0, "rg1");
+    assert_eq!(merged.datasets[1].0, "rg2");
+    assert_eq!(merged.datasets[1].1.total(), 3);
+}
@@ -0,0 +1,68 @@
+//! kyber makes a minimalistic heatmap of read length and read accuracy from bam or cram files.
+//!
+//! Besides the command line tool, the library can be used to embed these plots in other tools:
+//! collect the length and identity of reads from an iterator of [`bam::Record`](rust_htslib::bam::Record)
+//! with [`reads_from_records`], count them in a [`Histogram2D`] with [`reads_to_histogram`]
+//! and draw that to an [`RgbImage`](image::RgbImage) with [`render_heatmap`], with the counts
+//! transformed to the intensity of the pixels as set by a [`Scaling`](intensity::Scaling).
+//!
+//! ```no_run
+//! use kyber::transform::{AccuracyScale, Axes};
+//! use kyber::intensity::Scaling;
+//! use kyber::{reads_from_records, reads_to_histogram, render_heatmap, BackGround, Color, ReadLength};
+//! use rust_htslib::bam::{self, Read};
+//!
+//! let mut bam = bam::Reader::from_path("alignments.bam").unwrap();
+//! let reads = reads_from_records(bam.records().map(|r| r.unwrap()), false, ReadLength::Query);
+//! let histogram = reads_to_histogram(&reads, Axes::new(AccuracyScale::Percent));
+//! let scaling = Scaling::default();
+//! let image = render_heatmap(vec![histogram], BackGround::Black, vec![Color::Red], scaling);
+//! image.save("accuracy_heatmap.png").unwrap();
+//! ```
+
+use clap::ValueEnum;
+
+pub mod animate;
+pub mod axis_ticks;
+pub mod contour;
+pub mod extract_data;
+pub mod flowcell;
+pub mod gate;
+pub mod guides;
+pub mod histogram;
+pub mod identity;
+pub mod intensity;
+pub mod kyb;
+pub mod plot;
+pub mod report;
+pub mod sampling;
+pub mod stats;
+pub mod transform;
+pub mod utils;
+
+pub use extract_data::{reads_from_records, reads_to_histogram, ReadAccuracy, ReadLength};
+pub use histogram::Histogram2D;
+pub use plot::render_heatmap;
+
+/// Color used for a dataset in the heatmap
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum Color {
+    Red,
+    Green,
+    Blue,
+    Purple,
+    Yellow,
+}
+
+/// Color used for the background of the heatmap
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum BackGround {
+    Black,
+    White,
+}
+
+#[cfg(test)]
+#[ctor::ctor]
+fn init() {
+    env_logger::init();
+}
@@ -1,46 +1,186 @@
-use clap::{Parser, ValueEnum};
-use image::{Rgb, RgbImage};
-use log::{debug, info};
-use ndarray::{arr1, Array1};
-use std::collections::HashMap;
-
-pub mod axis_ticks;
-pub mod extract_data;
-pub mod identity;
-pub mod transform;
-pub mod utils;
-
-#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
-enum Color {
-    Red,
-    Green,
-    Blue,
-    Purple,
-    Yellow,
-}
+use clap::{ArgMatches, Args, CommandFactory, FromArgMatches, Parser, Subcommand};
+use kyber::extract_data::ReadLength;
+use kyber::flowcell::{self, ChannelValue};
+use kyber::gate::{self, Gate};
+use kyber::guides::{self, Guide};
+use kyber::histogram::HistogramKind;
+use kyber::intensity::{self, CountScale, SampleNorm, Scaling};
+use kyber::plot::{color_to_rgb, plot_calibration, plot_grid, render_contours, render_heatmap};
+use kyber::transform::{AccuracyScale, Axes};
+use kyber::{
+    animate, axis_ticks, extract_data, kyb, report, sampling, stats, utils, BackGround, Color,
+    Histogram2D,
+};
+use log::{info, warn};
+use std::path::Path;
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
-pub enum BackGround {
-    Black,
-    White,
-}
+mod config;
 
 // The arguments end up in the Cli struct
+// without a subcommand, the reads are extracted and plotted in a single run
 #[derive(Parser, Debug)]
 #[command(author, version, about="Tool to create a length-accuracy heatmap from a cram or bam file", long_about = None)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Cli {
-    /// cram or bam file(s), or use `-` to read a file from stdin with e.g. samtools view -h
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// cram or bam file(s), kyber file(s) (.kyb) with saved histograms, or use `-` to read a file from stdin with e.g. samtools view -h
     #[arg(short, long, value_parser, num_args = 0..=3, required = true)]
     input: Vec<String>,
 
-    /// Number of parallel decompression threads to use
+    /// Output file name(s), optionally with settings for this image as in heatmap.png:phred,log2, or a kyber file (.kyb) or table (.tsv) to save the histograms
+    #[arg(short, long, value_parser = utils::parse_output, num_args = 1.., default_value = "accuracy_heatmap.png")]
+    output: Vec<utils::OutputSpec>,
+
+    #[command(flatten)]
+    extract: ExtractArgs,
+
+    #[command(flatten)]
+    plot: PlotArgs,
+
+    /// Print the number of reads and bases of each dataset, and the subsampling that was applied
+    #[arg(long, value_parser, default_value_t = false)]
+    stats: bool,
+
+    /// TOML file with settings for the options that are not given on the command line, e.g. max-length = 4000000
+    #[arg(long, value_parser, global = true)]
+    config: Option<String>,
+
+    /// Settings for a type of reads, which are overridden by the configuration file and the command line
+    #[arg(long, value_enum, value_parser, global = true)]
+    preset: Option<config::Preset>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Save the histograms of cram or bam files to kyber files (.kyb) or tables (.tsv)
+    Extract {
+        /// cram or bam file(s), or use `-` to read a file from stdin with e.g. samtools view -h
+        #[arg(short, long, value_parser, num_args = 1.., required = true)]
+        input: Vec<String>,
+
+        /// Output kyber file(s) (.kyb) or table(s) (.tsv), optionally with the accuracy scale as in histograms.kyb:phred
+        #[arg(short, long, value_parser = utils::parse_output, num_args = 1.., required = true)]
+        output: Vec<utils::OutputSpec>,
+
+        #[command(flatten)]
+        axes: AxesArgs,
+
+        #[command(flatten)]
+        extract: ExtractArgs,
+
+        /// Print the number of reads and bases of each dataset, and the subsampling that was applied
+        #[arg(long, value_parser, default_value_t = false)]
+        stats: bool,
+    },
+    /// Plot the histograms saved in kyber files (.kyb)
+    Plot {
+        /// kyber file(s)
+        #[arg(short, long, value_parser, num_args = 1.., required = true)]
+        input: Vec<String>,
+
+        /// Output file name(s), optionally with settings for this image as in heatmap.png:phred,log2
+        #[arg(short, long, value_parser = utils::parse_output, num_args = 1.., default_value = "accuracy_heatmap.png")]
+        output: Vec<utils::OutputSpec>,
+
+        #[command(flatten)]
+        plot: PlotArgs,
+    },
+    /// Sum the histograms of kyber files (.kyb), e.g. made from chunks of a flowcell
+    Merge {
+        /// kyber files to merge
+        #[arg(value_parser, num_args = 2.., required = true)]
+        input: Vec<String>,
+
+        /// Output kyber file
+        #[arg(short, long, value_parser)]
+        output: String,
+    },
+    /// Print the number of reads and bases of each dataset, and the subsampling that was applied
+    Stats {
+        /// cram or bam file(s) or kyber file(s) (.kyb), or use `-` to read a file from stdin with e.g. samtools view -h
+        #[arg(short, long, value_parser, num_args = 1.., required = true)]
+        input: Vec<String>,
+
+        #[command(flatten)]
+        extract: ExtractArgs,
+    },
+    /// Compare the datasets of multiple files, labelled with their file name,
+    /// in an overlay or in a grid of panels for more than three datasets
+    Compare {
+        /// cram or bam files or kyber files (.kyb)
+        #[arg(short, long, value_parser, num_args = 2.., required = true)]
+        input: Vec<String>,
+
+        /// Output file name(s), optionally with settings for this image as in heatmap.png:phred,log2, or a kyber file (.kyb) or table (.tsv) to save the histograms
+        #[arg(short, long, value_parser = utils::parse_output, num_args = 1.., default_value = "accuracy_comparison.png")]
+        output: Vec<utils::OutputSpec>,
+
+        #[command(flatten)]
+        extract: ExtractArgs,
+
+        #[command(flatten)]
+        plot: PlotArgs,
+
+        /// Print the number of reads and bases of each dataset, and the subsampling that was applied
+        #[arg(long, value_parser, default_value_t = false)]
+        stats: bool,
+    },
+}
+
+/// Options to read the reads from cram or bam files
+#[derive(Args, Debug)]
+struct ExtractArgs {
+    /// Number of threads to use, divided between decompression and processing of records
     #[arg(short, long, value_parser, default_value_t = 4)]
     threads: usize,
 
-    /// Output file name
-    #[arg(short, long, value_parser, default_value_t = String::from("accuracy_heatmap.png"))]
-    output: String,
+    /// get reads from ubam file
+    #[arg(long, value_parser, default_value_t = false)]
+    ubam: bool,
+
+    /// Length of the reads: of the stored sequence, including hard clips, without soft clips or of the reference
+    #[arg(long, value_enum, value_parser, default_value_t = ReadLength::Query)]
+    length: ReadLength,
+
+    /// Combine the primary and supplementary alignments of a read into a single read with the identity of all alignments
+    #[arg(long, value_parser, default_value_t = false, conflicts_with_all = ["ubam", "calibration", "time", "flowcell", "max_reads"])]
+    per_read: bool,
 
+    /// Plot the accuracy predicted by the base qualities against the observed alignment accuracy
+    #[arg(long, value_parser, default_value_t = false, conflicts_with_all = ["ubam", "time"])]
+    calibration: bool,
+
+    /// Plot the accuracy against the time into the run, from the st tag of ONT reads and the DT field of their read group
+    #[arg(long, value_parser, default_value_t = false)]
+    time: bool,
+
+    /// Plot the mean accuracy or the number of reads of each channel (ch tag of ONT reads) on a map of the flow cell
+    #[arg(long, value_enum, value_parser, conflicts_with_all = ["calibration", "time", "split_by"])]
+    flowcell: Option<ChannelValue>,
+
+    /// Split the reads of a single input file in groups by RG, SM, BC or tag:XX
+    #[arg(long, value_parser = utils::parse_split_by, conflicts_with_all = ["calibration", "time"])]
+    split_by: Option<extract_data::SplitBy>,
+
+    /// Only use this fraction of the reads, selected deterministically by the hash of the read name
+    #[arg(long, value_parser = sampling::parse_fraction)]
+    fraction: Option<f64>,
+
+    /// Seed for the selection of reads with --fraction
+    #[arg(long, value_parser, default_value_t = 0, requires = "fraction")]
+    seed: u32,
+
+    /// Stop after reading this number of records from each input file, of which reads that
+    /// are skipped later, e.g. without base qualities, are not plotted
+    #[arg(long, value_parser)]
+    max_reads: Option<usize>,
+}
+
+/// Options for the appearance of the plot
+#[derive(Args, Debug)]
+struct PlotArgs {
     /// Color used for heatmap
     #[arg(short, long, value_enum, value_parser, num_args = 0..=3)]
     color: Option<Vec<Color>>,
@@ -49,351 +189,855 @@ struct Cli {
     #[arg(short, long, value_enum, value_parser, default_value_t = BackGround::Black)]
     background: BackGround,
 
+    #[command(flatten)]
+    axes: AxesArgs,
+
+    /// Transformation of the counts in each bin before these are scaled to the highest count
+    #[arg(long, value_enum, value_parser, default_value_t = CountScale::Linear)]
+    scale: CountScale,
+
+    /// Added to the counts before the transformation with --scale, such that bins with few reads stay visible
+    #[arg(long, value_parser = intensity::parse_positive, default_value_t = 1.0)]
+    pseudocount: f64,
+
+    /// Same as --scale log2
+    #[arg(long, value_parser, default_value_t = false, conflicts_with = "scale")]
+    normalize: bool,
+
+    /// Count of a bin at the lowest intensity of the color scale [default: 0]
+    #[arg(long, value_parser)]
+    vmin: Option<f64>,
+
+    /// Count of a bin at which the color scale saturates [default: the highest count]
+    #[arg(long, value_parser)]
+    vmax: Option<f64>,
+
+    /// Saturate the color scale at this quantile of the counts of the bins with reads, e.g. 0.99
+    #[arg(long, value_parser = intensity::parse_quantile, conflicts_with = "vmax")]
+    vmax_quantile: Option<f64>,
+
+    /// Normalization of each dataset, such that overlays of datasets with a different number of reads are comparable
+    #[arg(long, value_enum, value_parser, default_value_t = SampleNorm::None)]
+    sample_norm: SampleNorm,
+
+    /// Smooth the counts with a Gaussian kernel with this standard deviation in pixels
+    #[arg(long, value_parser = intensity::parse_positive)]
+    smooth: Option<f64>,
+
+    /// Draw this number of iso-density lines, of the other datasets over a heatmap of the first dataset
+    #[arg(long, value_parser = clap::value_parser!(u16).range(1..))]
+    contours: Option<u16>,
+
+    /// Plot each dataset, e.g. the groups from --split-by, in a grid of panels rather than an overlay
+    #[arg(long, value_parser, default_value_t = false)]
+    grid: bool,
+
+    /// Draw lines at the read length N50 and the median accuracy of each dataset
+    #[arg(long, value_parser, default_value_t = false)]
+    guides: bool,
+
+    /// Draw horizontal lines at these accuracies, in percent as in 95% or in phred as in Q20
+    #[arg(long, value_parser = guides::parse_hline, num_args = 1..)]
+    hline: Vec<Guide>,
+
+    /// Draw vertical lines at these read lengths, e.g. 10kb
+    #[arg(long, value_parser = guides::parse_vline, num_args = 1..)]
+    vline: Vec<Guide>,
+
+    /// Shade a rectangle with the percentage of reads and bases inside it, e.g. length>=10kb,accuracy>=Q20
+    #[arg(long, value_parser = gate::parse_gate)]
+    gate: Option<Gate>,
+
+    /// Save an interactive report with the histograms and the summary statistics to this HTML file, which needs no other files
+    #[arg(long, value_parser)]
+    html: Option<String>,
+
+    /// Save an animation of the heatmap as the reads accumulate during the run to this GIF file, by their start time (st tag) or their order in the file
+    #[arg(long, value_parser)]
+    animate: Option<String>,
+}
+
+impl PlotArgs {
+    // the scale of the counts of an output can differ from the one of the arguments
+    fn scaling(&self, scale: Option<CountScale>) -> Scaling {
+        let default = if self.normalize {
+            CountScale::Log2
+        } else {
+            self.scale
+        };
+        if let (Some(vmin), Some(vmax)) = (self.vmin, self.vmax) {
+            if vmin >= vmax {
+                panic!("\n\nERROR: --vmin ({vmin}) has to be lower than --vmax ({vmax})!");
+            }
+        }
+        Scaling {
+            scale: scale.unwrap_or(default),
+            pseudocount: self.pseudocount,
+            vmin: self.vmin,
+            vmax: self.vmax,
+            vmax_quantile: self.vmax_quantile,
+            sample_norm: self.sample_norm,
+            smooth: self.smooth,
+        }
+    }
+}
+
+/// Options for the scale and the ranges of the axes
+#[derive(Args, Debug)]
+struct AxesArgs {
     /// Plot accuracy in phred scale
     #[arg(short, long, value_parser, default_value_t = false)]
     phred: bool,
 
-    /// Normalize the counts in each bin with a log2
-    #[arg(long, value_parser, default_value_t = false)]
-    normalize: bool,
+    /// Longest read length on the x-axis [default: 1000000]
+    #[arg(long, value_parser)]
+    max_length: Option<usize>,
 
-    /// get reads from ubam file
-    #[arg(long, value_parser, default_value_t = false)]
-    ubam: bool,
+    /// Lowest accuracy on the y-axis, as percentage or phred score [default: 70 or 0]
+    #[arg(long, value_parser)]
+    min_accuracy: Option<f32>,
+
+    /// Highest accuracy on the y-axis, as percentage or phred score [default: 100 or 40]
+    #[arg(long, value_parser)]
+    max_accuracy: Option<f32>,
+
+    /// Latest time into the run on the x-axis of the time plot in hours [default: 72]
+    #[arg(long, value_parser)]
+    max_time: Option<f32>,
+}
+
+impl AxesArgs {
+    // the accuracy scale of an output can differ from the one of the arguments
+    fn axes(&self, phred: Option<bool>) -> Axes {
+        Axes::new(AccuracyScale::from_phred(phred.unwrap_or(self.phred)))
+            .with_ranges(self.max_length, self.min_accuracy, self.max_accuracy)
+            .and_then(|axes| axes.with_max_time(self.max_time))
+            .unwrap_or_else(|e| panic!("\n\nERROR: {e}!"))
+    }
+
+    // whether the axes differ from those that were explicitly requested
+    fn conflicts_with(&self, phred: Option<bool>, axes: &Axes) -> bool {
+        phred.is_some_and(|p| axes.scale.is_phred() != p)
+            || self.max_length.is_some_and(|l| l != axes.max_length)
+            || self.min_accuracy.is_some_and(|a| a != axes.min_accuracy)
+            || self.max_accuracy.is_some_and(|a| a != axes.max_accuracy)
+            || self.max_time.is_some_and(|t| t != axes.max_time)
+    }
+}
+
+// The reads of each dataset, or their histograms if these were saved in kyber files
+enum Data {
+    Reads(Vec<Vec<extract_data::ReadAccuracy>>),
+    Calibration(Vec<Vec<extract_data::CalibrationAccuracy>>),
+    Time(Vec<Vec<extract_data::TimeAccuracy>>),
+    // the reads in the order of the run, for an animation
+    Run(Vec<extract_data::RunReads>),
+    // the reads of each channel, with the value by which the channels are colored
+    Flowcell(ChannelValue, Vec<extract_data::Channels>),
+    Saved(Vec<Histogram2D>),
+}
+
+struct Datasets {
+    names: Vec<String>,
+    // whether the names are shown as labels in the plot, e.g. for groups of reads
+    labelled: bool,
+    data: Data,
+    metadata: kyb::Metadata,
+    sampling: sampling::Sampling,
 }
 
 fn main() {
     env_logger::init();
-    let args = Cli::parse();
-    let colors = assign_colors(&args);
-    let transform_accuracy = if args.phred {
-        transform::transform_accuracy_phred
-    } else {
-        transform::transform_accuracy_percent
+    let matches = Cli::command().get_matches();
+    let mut args = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    let config = config::Config::load(args.config.as_deref(), args.preset);
+    apply_config(&mut args, &config, &matches);
+    match args.command {
+        None => {
+            let animate = args.plot.animate.is_some();
+            let datasets = load_datasets(&args.input, &args.extract, animate);
+            if args.stats {
+                print_stats(&datasets);
+            }
+            write_outputs(&datasets, &args.output, &args.plot);
+        }
+        Some(Command::Extract {
+            input,
+            output,
+            axes,
+            extract,
+            stats,
+        }) => {
+            if let Some(o) = output
+                .iter()
+                .find(|o| !kyb::is_kyb(&o.path) && !kyb::is_tsv(&o.path))
+            {
+                panic!("\n\nERROR: output {} is not a kyber file (.kyb) or table (.tsv)!", o.path);
+            }
+            if input.iter().any(|f| kyb::is_kyb(f)) {
+                panic!("\n\nERROR: extract takes cram or bam files, use merge for kyber files!");
+            }
+            let datasets = load_datasets(&input, &extract, false);
+            if stats {
+                print_stats(&datasets);
+            }
+            for o in &output {
+                let histograms = datasets.histograms(&axes, o.phred);
+                save_histograms(&datasets, histograms, &o.path);
+            }
+        }
+        Some(Command::Plot {
+            input,
+            output,
+            plot,
+        }) => {
+            let datasets = load_saved(&input);
+            write_outputs(&datasets, &output, &plot);
+        }
+        Some(Command::Merge { input, output }) => {
+            for f in &input {
+                utils::is_file(f).unwrap_or_else(|_| panic!("Input file {f} is invalid",));
+            }
+            kyb::merge_kyb(&input, &output);
+        }
+        Some(Command::Stats { input, extract }) => {
+            print_stats(&load_datasets(&input, &extract, false));
+        }
+        Some(Command::Compare {
+            input,
+            output,
+            extract,
+            plot,
+            stats,
+        }) => {
+            if extract.split_by.is_some() {
+                panic!("\n\nERROR: compare does not support --split-by, which is used for a single input file!");
+            }
+            let mut datasets = load_datasets(&input, &extract, plot.animate.is_some());
+            datasets.labelled = true;
+            for name in datasets.names.iter_mut() {
+                if let Some(file_name) = Path::new(name).file_name() {
+                    *name = file_name.to_string_lossy().to_string();
+                }
+            }
+            if stats {
+                print_stats(&datasets);
+            }
+            write_outputs(&datasets, &output, &plot);
+        }
+    }
+}
+
+// the settings of the configuration file and the preset map onto the arguments
+// of the command, unless these were given on the command line
+fn apply_config(args: &mut Cli, config: &config::Config, matches: &ArgMatches) {
+    let command_matches = matches.subcommand().map_or(matches, |(_name, m)| m);
+    match &mut args.command {
+        None => {
+            args.extract.apply_config(config, matches);
+            args.plot.apply_config(config, matches);
+            config.apply_stats(&mut args.stats, matches);
+        }
+        Some(Command::Extract {
+            axes,
+            extract,
+            stats,
+            ..
+        }) => {
+            axes.apply_config(config, command_matches);
+            extract.apply_config(config, command_matches);
+            config.apply_stats(stats, command_matches);
+        }
+        Some(Command::Plot { plot, .. }) => plot.apply_config(config, command_matches),
+        Some(Command::Merge { .. }) => {}
+        Some(Command::Stats { extract, .. }) => extract.apply_config(config, command_matches),
+        Some(Command::Compare {
+            extract,
+            plot,
+            stats,
+            ..
+        }) => {
+            extract.apply_config(config, command_matches);
+            plot.apply_config(config, command_matches);
+            config.apply_stats(stats, command_matches);
+        }
+    }
+}
+
+// collect the reads of the input files, or of the groups of reads of a single input file,
+// which are kept in memory to make all outputs without reading the input again,
+// in the order of the run if these are animated
+fn load_datasets(input: &[String], args: &ExtractArgs, animate: bool) -> Datasets {
+    if input.iter().any(|f| kyb::is_kyb(f)) {
+        return load_saved(input);
+    }
+    if animate
+        && (args.calibration || args.time || args.flowcell.is_some() || args.split_by.is_some())
+    {
+        panic!("\n\nERROR: --animate cannot be used with --calibration, --time, --flowcell or --split-by!");
+    }
+    if animate && args.per_read {
+        panic!("\n\nERROR: --animate cannot be used with --per-read!");
+    }
+    if args.ubam && matches!(args.length, ReadLength::Aligned | ReadLength::ReferenceSpan) {
+        panic!(
+            "\n\nERROR: --length {} is only defined for aligned reads, not with --ubam!",
+            args.length.name()
+        );
+    }
+    let sampling = sampling::Sampling {
+        fraction: args.fraction,
+        seed: args.seed,
+        max_reads: args.max_reads,
     };
-    let mut hashmaps = vec![];
-    for f in args.input {
-        utils::is_file(&f).unwrap_or_else(|_| panic!("Input file {f} is invalid",));
-        let hashmap = extract_data::bam_to_hashmap(&f, args.threads, transform_accuracy, args.ubam);
-        if args.normalize {
-            hashmaps.push(extract_data::log_transform_hashmap(hashmap));
+    if sampling.is_active() {
+        info!("Subsampling reads with {:?}", sampling);
+    }
+    let mut metadata = kyb::Metadata::new(
+        if args.ubam {
+            kyb::Metric::PredictedAccuracy
         } else {
-            hashmaps.push(hashmap);
+            kyb::Metric::GapCompressedIdentity
+        },
+        extract_data::describe_filters(args.ubam, args.length, args.per_read, &sampling),
+        input.to_vec(),
+    );
+    if args.time {
+        metadata.filters.push(String::from("with a start time"));
+    }
+    metadata.grouped = args.split_by.is_some();
+    for f in input {
+        utils::is_file(f).unwrap_or_else(|_| panic!("Input file {f} is invalid",));
+    }
+    let (names, labelled, data) = if let Some(split_by) = &args.split_by {
+        if input.len() > 1 {
+            panic!("\n\nERROR: --split-by only supports a single input file!");
         }
+        let groups = extract_data::bam_to_grouped_reads(
+            &input[0],
+            args.threads,
+            args.ubam,
+            args.length,
+            args.per_read,
+            split_by,
+            &sampling,
+        );
+        let (groups, reads) = groups.into_iter().unzip();
+        (groups, true, Data::Reads(reads))
+    } else if args.calibration {
+        // the input files are read concurrently, and kept in the order of the arguments
+        let reads = extract_data::process_files_concurrently(input, args.threads, |f, t| {
+            extract_data::bam_to_calibration_reads(f, t, &sampling)
+        });
+        (input.to_vec(), false, Data::Calibration(reads))
+    } else if animate {
+        let runs = extract_data::process_files_concurrently(input, args.threads, |f, t| {
+            extract_data::bam_to_run_reads(f, t, args.ubam, args.length, &sampling)
+        });
+        (input.to_vec(), false, Data::Run(runs))
+    } else if let Some(value) = args.flowcell {
+        let channels = extract_data::process_files_concurrently(input, args.threads, |f, t| {
+            extract_data::bam_to_channels(f, t, args.ubam, args.length, &sampling)
+        });
+        (input.to_vec(), false, Data::Flowcell(value, channels))
+    } else if args.time {
+        let reads = extract_data::process_files_concurrently(input, args.threads, |f, t| {
+            extract_data::bam_to_time_reads(f, t, args.ubam, args.length, &sampling)
+        });
+        (input.to_vec(), false, Data::Time(reads))
+    } else {
+        let reads = extract_data::process_files_concurrently(input, args.threads, |f, t| {
+            extract_data::bam_to_reads(f, t, args.ubam, args.length, args.per_read, &sampling)
+        });
+        (input.to_vec(), false, Data::Reads(reads))
+    };
+    Datasets {
+        names,
+        labelled,
+        data,
+        metadata,
+        sampling,
     }
-    plot_heatmap(
-        hashmaps,
-        args.background,
-        colors,
-        &args.output,
-        transform_accuracy,
-        args.phred,
-    );
 }
 
-fn assign_colors(args: &Cli) -> Vec<Color> {
-    // check if there are equal number of arguments for the input and color parameters
-    let default_colors = [Color::Red, Color::Blue, Color::Green];
-    let colors = match &args.color {
-        Some(c) => {
-            if c.len() != args.input.len() {
-                panic!(
-                    "\n\nERROR: number of input files ({}) and colors ({}) do not match!",
-                    args.input.len(),
-                    c.len()
-                );
+// histograms that were saved before only have to be plotted
+fn load_saved(input: &[String]) -> Datasets {
+    let mut files = vec![];
+    for f in input {
+        if !kyb::is_kyb(f) {
+            panic!("\n\nERROR: kyber files (.kyb) cannot be combined with other input files!");
+        }
+        utils::is_file(f).unwrap_or_else(|_| panic!("Input file {f} is invalid",));
+        files.push(kyb::read_kyb(f));
+    }
+    let metadata = kyb::merge_metadata(input, files.iter().map(|f| &f.metadata));
+    kyb::check_axes(input, &files);
+    let (names, histograms): (Vec<_>, Vec<_>) = files.into_iter().flat_map(|f| f.datasets).unzip();
+    Datasets {
+        // datasets of groups of reads are labelled, as with --split-by
+        labelled: names.len() > input.len(),
+        names,
+        data: Data::Saved(histograms),
+        metadata,
+        sampling: sampling::Sampling::default(),
+    }
+}
+
+impl Datasets {
+    // make the histograms with the requested axes, with the accuracy in phred scale if
+    // requested for this output, which cannot be changed for saved histograms
+    fn histograms(&self, args: &AxesArgs, phred: Option<bool>) -> Vec<Histogram2D> {
+        let phred = phred.or(args.phred.then_some(true));
+        match &self.data {
+            Data::Saved(saved) => {
+                if saved.iter().any(|h| args.conflicts_with(phred, &h.axes())) {
+                    panic!("\n\nERROR: the histograms in the kyber file(s) cannot be plotted on other axes!");
+                }
+                saved.clone()
+            }
+            Data::Calibration(calibration) => calibration
+                .iter()
+                .map(|c| extract_data::calibration_to_histogram(c, args.axes(phred)))
+                .collect(),
+            Data::Time(time) => time
+                .iter()
+                .map(|t| extract_data::time_to_histogram(t, args.axes(phred)))
+                .collect(),
+            Data::Reads(reads) => reads
+                .iter()
+                .map(|r| extract_data::reads_to_histogram(r, args.axes(phred)))
+                .collect(),
+            Data::Run(runs) => runs
+                .iter()
+                .map(|r| extract_data::reads_to_histogram(&r.reads, args.axes(phred)))
+                .collect(),
+            Data::Flowcell(..) => {
+                panic!("\n\nERROR: the map of the flow cell is only saved as an image, not as histograms!")
             }
-            c
         }
-        None => &default_colors
+    }
+
+    // the length and identity of the reads of each dataset, which are not kept in kyber files
+    fn reads(&self) -> Option<Vec<&[extract_data::ReadAccuracy]>> {
+        match &self.data {
+            Data::Reads(reads) => Some(reads.iter().map(Vec::as_slice).collect()),
+            Data::Run(runs) => Some(runs.iter().map(|r| r.reads.as_slice()).collect()),
+            _ => None,
+        }
+    }
+}
+
+fn print_stats(datasets: &Datasets) {
+    stats::print_stats(&summaries(datasets), &datasets.sampling);
+}
+
+fn summaries(datasets: &Datasets) -> Vec<stats::Summary> {
+    match &datasets.data {
+        Data::Saved(saved) => datasets
+            .names
             .iter()
-            .cycle()
-            .take(args.input.len())
-            .cloned()
-            .collect::<Vec<Color>>(),
-    };
-    colors.to_owned()
+            .zip(saved)
+            .map(|(name, histogram)| stats::Summary {
+                name: name.clone(),
+                reads: histogram.total() as usize,
+                bases: None,
+            })
+            .collect::<Vec<_>>(),
+        Data::Calibration(calibration) => datasets
+            .names
+            .iter()
+            .zip(calibration)
+            .map(|(name, c)| stats::Summary {
+                name: name.clone(),
+                reads: c.len(),
+                bases: None,
+            })
+            .collect(),
+        Data::Time(time) => datasets
+            .names
+            .iter()
+            .zip(time)
+            .map(|(name, t)| stats::Summary {
+                name: name.clone(),
+                reads: t.len(),
+                bases: Some(t.iter().map(|r| u64::from(r.length)).sum()),
+            })
+            .collect(),
+        Data::Reads(reads) => datasets
+            .names
+            .iter()
+            .zip(reads)
+            .map(|(name, r)| stats::Summary::from_reads(name, r))
+            .collect(),
+        Data::Run(runs) => datasets
+            .names
+            .iter()
+            .zip(runs)
+            .map(|(name, r)| stats::Summary::from_reads(name, &r.reads))
+            .collect(),
+        Data::Flowcell(_value, channels) => datasets
+            .names
+            .iter()
+            .zip(channels)
+            .map(|(name, c)| stats::Summary {
+                name: name.clone(),
+                reads: c.values().map(|reads| reads.reads).sum(),
+                bases: Some(c.values().map(|reads| reads.bases).sum()),
+            })
+            .collect(),
+    }
 }
 
-fn max_of_hashmaps(hashmaps: &Vec<HashMap<(usize, usize), i32>>) -> f32 {
-    let mut maxes = vec![];
-    for h in hashmaps {
-        let max_value = *h
-            .values()
-            .max()
-            .expect("ERROR could not get max value of histogram");
-        maxes.push(max_value);
-    }
-    *maxes
-        .iter()
-        .max()
-        .expect("Error getting maximum of hashmaps.") as f32
+// every output is made from the datasets collected above, without reading the input again
+fn write_outputs(datasets: &Datasets, outputs: &[utils::OutputSpec], plot: &PlotArgs) {
+    check_colors(datasets, plot);
+    // the reads of every frame would be downsampled separately
+    if plot.animate.is_some() && plot.sample_norm == SampleNorm::Downsample {
+        panic!("\n\nERROR: --animate cannot be used with --sample-norm downsample!");
+    }
+    if let Data::Flowcell(value, channels) = &datasets.data {
+        write_flowcells(datasets, *value, channels, outputs, plot);
+        return;
+    }
+    for output in outputs {
+        let histograms = datasets.histograms(&plot.axes, output.phred);
+        if kyb::is_kyb(&output.path) || kyb::is_tsv(&output.path) {
+            save_histograms(datasets, histograms, &output.path);
+            continue;
+        }
+        info!("Creating {}", output.path);
+        // the plots of multiple datasets without a legend are distinguished by their color
+        let labels = if datasets.labelled || plot.grid {
+            &datasets.names[..]
+        } else {
+            &[]
+        };
+        let scaling = plot.scaling(output.scale);
+        create_plot(
+            plot,
+            labels,
+            histograms,
+            datasets.reads(),
+            scaling,
+            &output.path,
+        );
+    }
+    if let Some(path) = &plot.html {
+        write_report(datasets, plot, path);
+    }
+    if let Some(path) = &plot.animate {
+        write_animation(datasets, plot, path);
+    }
 }
 
-fn reads_to_intensity(
-    hashmap: &HashMap<(usize, usize), i32>,
-    color: Color,
-    maxval: f32,
-    background: BackGround,
-) -> HashMap<(usize, usize), Array1<u8>> {
-    let color = match color {
-        Color::Red => match background {
-            BackGround::White => arr1(&[255.0, 0.0, 0.0]),
-            BackGround::Black => arr1(&[1.0, 0.0, 0.0]),
-        },
-        Color::Green => match background {
-            BackGround::White => arr1(&[0.0, 255.0, 0.0]),
-            BackGround::Black => arr1(&[0.0, 1.0, 0.0]),
-        },
-        Color::Blue => match background {
-            BackGround::White => arr1(&[0.0, 0.0, 255.0]),
-            BackGround::Black => arr1(&[0.0, 0.0, 1.0]),
-        },
-        Color::Purple => match background {
-            BackGround::White => arr1(&[255.0, 0.0, 255.0]),
-            BackGround::Black => arr1(&[1.0, 0.0, 1.0]),
-        },
-        Color::Yellow => match background {
-            BackGround::White => arr1(&[255.0, 255.0, 0.0]),
-            BackGround::Black => arr1(&[1.0, 1.0, 0.0]),
-        },
+// the flow cells of all datasets are drawn in a single image, in the colors of an overlay
+fn write_flowcells(
+    datasets: &Datasets,
+    value: ChannelValue,
+    channels: &[extract_data::Channels],
+    outputs: &[utils::OutputSpec],
+    plot: &PlotArgs,
+) {
+    if plot.html.is_some() || plot.animate.is_some() {
+        warn!("The HTML report and the animation are not made for --flowcell");
+    }
+    // the flow cells of multiple datasets are labelled with their names
+    let labels = if datasets.labelled || channels.len() > 1 {
+        &datasets.names[..]
+    } else {
+        &[]
     };
-    let mut new_hashmap = HashMap::new();
-    for ((length, accuracy), count) in hashmap {
-        let intensity = *count as f32 / maxval * 255.0;
-        let entry = new_hashmap
-            .entry((*length, *accuracy))
-            .or_insert(arr1(&[0, 0, 0]));
-        if background == BackGround::White {
-            *entry = (color.clone() * (intensity / 255.0)).mapv(|x| (x * 255.0) as u8);
-        } else {
-            *entry = (color.clone() * intensity).mapv(|x| x as u8);
+    let colors = assign_colors(&plot.color, channels.len());
+    for output in outputs {
+        if kyb::is_kyb(&output.path) || kyb::is_tsv(&output.path) {
+            panic!(
+                "\n\nERROR: the map of the flow cell is only saved as an image, not to {}!",
+                output.path
+            );
         }
+        info!("Creating {}", output.path);
+        let image = flowcell::render_flowcell(
+            &channels.iter().collect::<Vec<_>>(),
+            labels,
+            value,
+            &plot.axes.axes(output.phred),
+            plot.background,
+            colors.clone(),
+            plot.scaling(output.scale),
+        );
+        info!("Saving image");
+        image.save(&output.path).expect("Error while saving image");
     }
-    new_hashmap
 }
 
-fn combine_hashmaps(
-    hashmaps: &Vec<HashMap<(usize, usize), i32>>,
-    colors: Vec<Color>,
-    background: BackGround,
-) -> Vec<HashMap<(usize, usize), Array1<u8>>> {
-    let maxval = max_of_hashmaps(hashmaps);
-    let mut new_hashmaps = vec![];
-    for (hashmap, color) in hashmaps.iter().zip(colors) {
-        new_hashmaps.push(reads_to_intensity(hashmap, color, maxval, background));
+// the animation has the same datasets and colors as an overlay, with the axes of the plot arguments
+fn write_animation(datasets: &Datasets, plot: &PlotArgs, path: &str) {
+    let Data::Run(runs) = &datasets.data else {
+        warn!("The animation is only made from cram or bam files, not from kyber files");
+        return;
+    };
+    if runs.len() > 3 {
+        warn!("The animation is only made for up to three datasets");
+        return;
     }
-    new_hashmaps
+    let labels = if datasets.labelled {
+        &datasets.names[..]
+    } else {
+        &[]
+    };
+    animate::write_animation(
+        path,
+        &runs.iter().collect::<Vec<_>>(),
+        labels,
+        plot.axes.axes(None),
+        plot.background,
+        assign_colors(&plot.color, runs.len()),
+        plot.scaling(None),
+    );
 }
 
-fn plot_heatmap(
-    hashmaps: Vec<HashMap<(usize, usize), i32>>,
-    background: BackGround,
-    chosen_color: Vec<Color>,
-    output: &str,
-    transform_accuracy: fn(f32) -> usize,
-    phred: bool,
-) {
-    let mut image = match background {
-        BackGround::Black => RgbImage::from_pixel(601, 601, Rgb([0, 0, 0])),
-        BackGround::White => RgbImage::from_pixel(601, 601, Rgb([255, 255, 255])),
+// the report has the same datasets and colors as the images, with the axes of the plot arguments
+fn write_report(datasets: &Datasets, plot: &PlotArgs, path: &str) {
+    let histograms = datasets.histograms(&plot.axes, None);
+    if histograms[0].kind() != HistogramKind::LengthAccuracy {
+        warn!("The HTML report is only made for the length and accuracy of reads, not for --calibration or --time");
+        return;
+    }
+    // the colors of a grid, of which only the first is used, are not those of the report
+    let colors = match &plot.color {
+        Some(colors) if colors.len() == histograms.len() => colors.clone(),
+        _ => assign_colors(&None, histograms.len()),
+    };
+    let file = kyb::KybFile {
+        metadata: datasets.metadata.clone(),
+        datasets: datasets.names.iter().cloned().zip(histograms).collect(),
+    };
+    report::write_html(
+        path,
+        &file,
+        &summaries(datasets),
+        &colors,
+        plot.background,
+        plot.scaling(None),
+    );
+}
+
+// the counts are saved without transformation, such that they can be merged
+fn save_histograms(datasets: &Datasets, histograms: Vec<Histogram2D>, path: &str) {
+    info!("Saving histograms to {path}");
+    let file = kyb::KybFile {
+        metadata: datasets.metadata.clone(),
+        datasets: datasets.names.iter().cloned().zip(histograms).collect(),
     };
+    if kyb::is_kyb(path) {
+        kyb::write_kyb(path, &file);
+    } else {
+        kyb::write_tsv(path, &file);
+    }
+}
 
-    if hashmaps.len() == 1 {
-        // Creating a plot with just a single dataset
-        let hashmap = &hashmaps[0];
-        info!(
-            "Constructing figure with {} colored pixels",
-            hashmap.values().len()
+// choose the type of plot based on the arguments, with the reads of the histograms
+// if these are available for the exact fractions in the gate
+fn create_plot(
+    args: &PlotArgs,
+    labels: &[String],
+    histograms: Vec<Histogram2D>,
+    reads: Option<Vec<&[extract_data::ReadAccuracy]>>,
+    scaling: Scaling,
+    output: &str,
+) {
+    let kind = histograms[0].kind();
+    let calibration = kind == HistogramKind::Calibration;
+    // an overlay of more than three groups is not informative
+    let grid = !labels.is_empty() && (args.grid || histograms.len() > 3);
+    if args.contours.is_some() && (calibration || grid) {
+        warn!("Contours are only drawn on a heatmap, not on a calibration plot or a grid");
+    }
+    // the guides and the gate are placed by read length
+    let length = kind == HistogramKind::LengthAccuracy && !grid;
+    let lines = args.guides || !args.hline.is_empty() || !args.vline.is_empty();
+    if lines && !length {
+        warn!("Guides are only drawn on a heatmap of read length, not on a calibration or time plot or a grid");
+    }
+    if args.gate.is_some() && !length {
+        warn!("The gate is only drawn on a heatmap of read length, not on a calibration or time plot or a grid");
+    }
+    if calibration {
+        let colors = assign_colors(&args.color, histograms.len());
+        plot_calibration(histograms, args.background, colors, scaling, output);
+    } else if grid {
+        info!("Plotting {} groups in a grid", histograms.len());
+        let color = args
+            .color
+            .as_ref()
+            .and_then(|c| c.first().copied())
+            .unwrap_or(Color::Red);
+        plot_grid(
+            labels.to_vec(),
+            histograms,
+            args.background,
+            color,
+            scaling,
+            output,
         );
-        debug!("Constructing figure with {:?}", hashmap);
-        // All counts are scaled to the max value
-        let max_value = hashmaps[0]
-            .values()
-            .max()
-            .expect("ERROR could not get max value of histogram");
-        debug!("Max value of histogram: {}", max_value);
-        // only do the code below in debug mode
-        if log::log_enabled!(log::Level::Debug) {
-            // debug the length and accuracy of the hashmap with the highest count
-            let (length, accuracy) = hashmap
-                .iter()
-                .max_by_key(|(_key, value)| *value)
-                .expect("ERROR could not get max value of histogram")
-                .0;
-            debug!("Length: {}, Accuracy: {} of max value", length, accuracy);
+    } else {
+        let colors = assign_colors(&args.color, histograms.len());
+        let axes = histograms[0].axes();
+        // the guides of the datasets are in their color, and the given lines in the color of the ticks
+        let mut guides = vec![];
+        if args.guides && length {
+            for (histogram, color) in histograms.iter().zip(colors.iter()) {
+                let rgb = Some(color_to_rgb(*color));
+                guides.extend(Guide::n50(histogram).map(|g| (g, rgb)));
+                guides.extend(Guide::median_accuracy(histogram).map(|g| (g, rgb)));
+            }
         }
-        // Iterate over the hashmap to fill in bins and color pixels accordingly
-        for ((length, accuracy), count) in hashmap {
-            let intensity = (*count as f32 / *max_value as f32 * 255.0) as u8;
-            let color = match chosen_color[0] {
-                Color::Red => {
-                    if background == BackGround::White {
-                        Rgb([255, 255 - intensity, 255 - intensity])
-                    } else {
-                        Rgb([intensity, 0, 0])
-                    }
-                }
-                Color::Green => {
-                    if background == BackGround::White {
-                        Rgb([255 - intensity, 255, 255 - intensity])
-                    } else {
-                        Rgb([0, intensity, 0])
-                    }
-                }
-                Color::Blue => {
-                    if background == BackGround::White {
-                        Rgb([255 - intensity, 255 - intensity, 255])
-                    } else {
-                        Rgb([0, 0, intensity])
-                    }
-                }
-                Color::Purple => {
-                    if background == BackGround::White {
-                        Rgb([255, 255 - intensity, 255])
-                    } else {
-                        Rgb([intensity, 0, intensity])
-                    }
+        let mut fractions = vec![];
+        if let Some(gate) = args.gate.as_ref().filter(|_| length) {
+            for (index, (histogram, color)) in histograms.iter().zip(colors.iter()).enumerate() {
+                let fraction = match &reads {
+                    Some(reads) => gate.read_fractions(reads[index]),
+                    None => gate.fractions(histogram),
+                };
+                if let Some((reads, bases)) = fraction {
+                    let name = labels
+                        .get(index)
+                        .map_or(String::new(), |l| format!("{l}: "));
+                    info!(
+                        "{name}{:.1}% of reads and {:.1}% of bases in the gate",
+                        reads * 100.0,
+                        bases * 100.0
+                    );
+                    fractions.push(((reads, bases), color_to_rgb(*color)));
                 }
-                Color::Yellow => {
-                    if background == BackGround::White {
-                        Rgb([255, 255, 255 - intensity])
-                    } else {
-                        Rgb([intensity, intensity, 0])
-                    }
-                }
-            };
-            image.put_pixel(*length as u32, *accuracy as u32, color);
+            }
         }
-    } else {
-        // Creating a plot of multiple datasets
-        let default = arr1(&[0, 0, 0]);
-        let hashmaps = combine_hashmaps(&hashmaps, chosen_color, background);
-        // Iterate over the first hashmap, and call .get for the remaining hashmaps
-        // If that bin is unused in one of the remaining hashmaps the default (0, 0, 0) is added
-        for ((length, accuracy), arr) in &hashmaps[0] {
-            let summed_arr = if hashmaps.len() == 2 {
-                arr + hashmaps[1].get(&(*length, *accuracy)).unwrap_or(&default)
-            } else {
-                arr + hashmaps[1].get(&(*length, *accuracy)).unwrap_or(&default)
-                    + hashmaps[2].get(&(*length, *accuracy)).unwrap_or(&default)
-            };
-            let arr: [u8; 3] = summed_arr
-                .clone()
-                .into_raw_vec_and_offset()
-                .0
-                .try_into()
-                .unwrap();
-            // Use the summed RGB arrays to fill in the pixel
-            image.put_pixel(*length as u32, *accuracy as u32, Rgb(arr));
+        if length {
+            guides.extend(
+                args.vline
+                    .iter()
+                    .chain(&args.hline)
+                    .map(|g| (g.clone(), None)),
+            );
+        }
+        let mut image = match args.contours {
+            Some(levels) => render_contours(
+                histograms,
+                args.background,
+                colors.clone(),
+                scaling,
+                levels as usize,
+            ),
+            None => render_heatmap(histograms, args.background, colors.clone(), scaling),
+        };
+        // the labels of the guides are moved away from those of the gate
+        let mut placed = vec![];
+        if let Some(gate) = args.gate.as_ref().filter(|_| length) {
+            image = gate::add_gate(image, &axes, args.background, gate, &fractions, &mut placed);
         }
+        if !guides.is_empty() {
+            image = guides::add_guides(image, &axes, args.background, &guides, &mut placed);
+        }
+        if !labels.is_empty() {
+            let legend = labels
+                .iter()
+                .cloned()
+                .zip(colors.into_iter().map(color_to_rgb))
+                .collect::<Vec<_>>();
+            image = axis_ticks::add_legend(image, &legend);
+        }
+        info!("Saving image");
+        image.save(output).expect("Error while saving image");
     }
-    info!("Adding axis ticks");
-    image = axis_ticks::add_ticks(image, transform_accuracy, phred, background);
+}
 
-    info!("Saving image");
-    image.save(output).expect("Error while saving image");
+// the number of colors has to match the number of datasets, except in a grid which only uses
+// the first color, which is checked before any output is written
+fn check_colors(datasets: &Datasets, plot: &PlotArgs) {
+    let grid = plot.grid || (datasets.labelled && datasets.names.len() > 3);
+    if let Some(colors) = plot.color.as_ref().filter(|_| !grid) {
+        assign_colors(&Some(colors.clone()), datasets.names.len());
+    }
 }
 
-#[cfg(test)]
-#[ctor::ctor]
-fn init() {
-    env_logger::init();
+fn assign_colors(color: &Option<Vec<Color>>, datasets: usize) -> Vec<Color> {
+    // check if there are equal number of arguments for the datasets and color parameters
+    let default_colors = [Color::Red, Color::Blue, Color::Green];
+    let colors = match color {
+        Some(c) => {
+            if c.len() != datasets {
+                panic!(
+                    "\n\nERROR: number of datasets ({}) and colors ({}) do not match!",
+                    datasets,
+                    c.len()
+                );
+            }
+            c
+        }
+        None => &default_colors
+            .iter()
+            .cycle()
+            .take(datasets)
+            .cloned()
+            .collect::<Vec<Color>>(),
+    };
+    colors.to_owned()
 }
 
 #[test]
 fn verify_app() {
-    use clap::CommandFactory;
     Cli::command().debug_assert()
 }
 
 #[test]
-fn test_single_file() {
-    let hashmap = extract_data::bam_to_hashmap(
-        "test-data/small-test-phased.bam",
-        4,
-        transform::transform_accuracy_percent,
-        false,
-    );
-    plot_heatmap(
-        vec![hashmap],
-        BackGround::Black,
-        vec![Color::Purple],
-        "accuracy_heatmap_percent_on_black.png",
-        transform::transform_accuracy_percent,
-        false,
-    );
+fn test_default_invocation() {
+    let args = Cli::parse_from(["kyber", "-i", "reads.bam", "--phred"]);
+    assert!(args.command.is_none());
+    assert!(args.plot.axes.phred);
+    let args = Cli::parse_from(["kyber", "merge", "a.kyb", "b.kyb", "-o", "total.kyb"]);
+    assert!(matches!(args.command, Some(Command::Merge { .. })));
+    assert!(Cli::try_parse_from(["kyber"]).is_err());
+    // --max-reads could stop between the alignments of a read that are combined
+    let args = ["kyber", "-i", "bam", "--per-read", "--max-reads", "10"];
+    assert!(Cli::try_parse_from(args).is_err());
 }
 
 #[test]
-fn test_single_file_ubam() {
-    let hashmap = extract_data::bam_to_hashmap(
-        "test-data/small-test-phased.bam",
-        4,
-        transform::transform_accuracy_percent,
-        true,
-    );
-    plot_heatmap(
-        vec![hashmap],
-        BackGround::Black,
-        vec![Color::Purple],
-        "accuracy_heatmap_percent_on_black_ubam.png",
-        transform::transform_accuracy_percent,
-        false,
-    );
-}
-
-#[test]
-#[ignore]
-fn test_single_file_from_de() {
-    let hashmap = extract_data::bam_to_hashmap(
-        "test-data/small-test-phased_de.bam",
-        4,
-        transform::transform_accuracy_percent,
-        false,
-    );
-    plot_heatmap(
-        vec![hashmap],
-        BackGround::Black,
-        vec![Color::Purple],
-        "accuracy_heatmap_percent_on_black_from_de.png",
-        transform::transform_accuracy_percent,
-        false,
-    );
-}
-
-#[test]
-fn test_single_file_black_phred() {
-    let hashmap = extract_data::bam_to_hashmap(
-        "test-data/small-test-phased.bam",
-        4,
-        transform::transform_accuracy_phred,
-        false,
-    );
-    plot_heatmap(
-        vec![hashmap],
-        BackGround::Black,
-        vec![Color::Purple],
-        "accuracy_heatmap_phred_on_black.png",
-        transform::transform_accuracy_percent,
-        true,
-    );
+fn test_preset() {
+    let command = [
+        "kyber",
+        "plot",
+        "-i",
+        "a.kyb",
+        "--preset=hifi",
+        "--min-accuracy=30",
+    ];
+    let matches = Cli::command().get_matches_from(command);
+    let mut args = Cli::from_arg_matches(&matches).unwrap();
+    let config = config::Config::load(args.config.as_deref(), args.preset);
+    apply_config(&mut args, &config, &matches);
+    let Some(Command::Plot { plot, .. }) = args.command else {
+        panic!("expected the plot subcommand");
+    };
+    // the command line overrides the preset
+    assert!(plot.axes.phred);
+    assert_eq!(plot.axes.min_accuracy, Some(30.0));
+    assert_eq!(plot.axes.max_accuracy, Some(50.0));
+    assert_eq!(plot.axes.max_length, None);
 }
 
 #[test]
-fn test_single_file_phred() {
-    let hashmap = extract_data::bam_to_hashmap(
-        "test-data/small-test-phased.bam",
-        4,
-        transform::transform_accuracy_phred,
-        false,
-    );
-    plot_heatmap(
-        vec![hashmap],
-        BackGround::White,
-        vec![Color::Red],
-        "accuracy_heatmap_phred_on_white.png",
-        transform::transform_accuracy_phred,
-        true,
-    );
+#[should_panic]
+fn test_check_colors() {
+    let args = Cli::parse_from(["kyber", "-i", "reads.bam", "--color", "red"]);
+    let datasets = |labelled| Datasets {
+        names: vec![String::from("group"); 4],
+        labelled,
+        data: Data::Saved(vec![]),
+        metadata: kyb::Metadata::new(kyb::Metric::GapCompressedIdentity, vec![], vec![]),
+        sampling: sampling::Sampling::default(),
+    };
+    // a grid of the groups only uses the first color, an overlay needs a color for each dataset
+    check_colors(&datasets(true), &args.plot);
+    check_colors(&datasets(false), &args.plot);
 }
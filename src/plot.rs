@@ -0,0 +1,560 @@
+use image::{imageops, Rgb, RgbImage};
+use imageproc::drawing::draw_line_segment_mut;
+use log::{debug, info};
+use ndarray::{arr1, Array1};
+use std::collections::{BTreeSet, HashMap};
+
+use crate::histogram::{Histogram2D, HistogramKind};
+use crate::intensity::Scaling;
+use crate::transform::Axes;
+use crate::{axis_ticks, contour, transform, BackGround, Color};
+
+/// The color of a dataset at full intensity, e.g. for labels
+pub fn color_to_rgb(color: Color) -> Rgb<u8> {
+    match color {
+        Color::Red => Rgb([255, 0, 0]),
+        Color::Green => Rgb([0, 255, 0]),
+        Color::Blue => Rgb([0, 0, 255]),
+        Color::Purple => Rgb([255, 0, 255]),
+        Color::Yellow => Rgb([255, 255, 0]),
+    }
+}
+
+/// The color of a dataset at an intensity (0-255) on the background, as for a single dataset
+pub fn color_at_intensity(color: Color, background: BackGround, intensity: u8) -> Rgb<u8> {
+    let full = color_to_rgb(color).0;
+    Rgb(match background {
+        BackGround::Black => full.map(|c| (u16::from(c) * u16::from(intensity) / 255) as u8),
+        BackGround::White => {
+            full.map(|c| 255 - ((255 - u16::from(c)) * u16::from(intensity) / 255) as u8)
+        }
+    })
+}
+
+fn reads_to_intensity(
+    histogram: &Histogram2D,
+    color: Color,
+    limits: (f64, f64),
+    background: BackGround,
+    scaling: Scaling,
+) -> HashMap<(usize, usize), Array1<u8>> {
+    let color = match color {
+        Color::Red => match background {
+            BackGround::White => arr1(&[255.0, 0.0, 0.0]),
+            BackGround::Black => arr1(&[1.0, 0.0, 0.0]),
+        },
+        Color::Green => match background {
+            BackGround::White => arr1(&[0.0, 255.0, 0.0]),
+            BackGround::Black => arr1(&[0.0, 1.0, 0.0]),
+        },
+        Color::Blue => match background {
+            BackGround::White => arr1(&[0.0, 0.0, 255.0]),
+            BackGround::Black => arr1(&[0.0, 0.0, 1.0]),
+        },
+        Color::Purple => match background {
+            BackGround::White => arr1(&[255.0, 0.0, 255.0]),
+            BackGround::Black => arr1(&[1.0, 0.0, 1.0]),
+        },
+        Color::Yellow => match background {
+            BackGround::White => arr1(&[255.0, 255.0, 0.0]),
+            BackGround::Black => arr1(&[1.0, 1.0, 0.0]),
+        },
+    };
+    let mut new_hashmap = HashMap::new();
+    for ((length, accuracy), count) in histogram.iter() {
+        let intensity = scaling.intensity(count, limits) as f32;
+        let entry = new_hashmap
+            .entry((length, accuracy))
+            .or_insert(arr1(&[0, 0, 0]));
+        if background == BackGround::White {
+            *entry = (color.clone() * (intensity / 255.0)).mapv(|x| (x * 255.0) as u8);
+        } else {
+            *entry = (color.clone() * intensity).mapv(|x| x as u8);
+        }
+    }
+    new_hashmap
+}
+
+fn combine_histograms(
+    histograms: &[Histogram2D],
+    colors: Vec<Color>,
+    background: BackGround,
+    scaling: Scaling,
+) -> Vec<HashMap<(usize, usize), Array1<u8>>> {
+    // all datasets are scaled to the same limits
+    let limits = scaling.limits(histograms);
+    debug!("Limits of the color scale: {:?}", limits);
+    let mut new_hashmaps = vec![];
+    for (histogram, color) in histograms.iter().zip(colors) {
+        new_hashmaps.push(reads_to_intensity(
+            histogram, color, limits, background, scaling,
+        ));
+    }
+    new_hashmaps
+}
+
+// datasets can only be drawn on the same axes if they were binned in the same way
+fn check_axes(histograms: &[Histogram2D]) {
+    let first = histograms.first().expect("ERROR no histograms to plot");
+    for histogram in histograms {
+        if histogram.kind() != first.kind() || histogram.axes() != first.axes() {
+            panic!("\n\nERROR: cannot plot histograms with different axes in the same figure!");
+        }
+    }
+}
+
+/// Plot one or up to three overlaid datasets and save the image
+pub fn plot_heatmap(
+    histograms: Vec<Histogram2D>,
+    background: BackGround,
+    chosen_color: Vec<Color>,
+    scaling: Scaling,
+    output: &str,
+) {
+    let image = render_heatmap(histograms, background, chosen_color, scaling);
+    info!("Saving image");
+    image.save(output).expect("Error while saving image");
+}
+
+/// Plot one or up to three overlaid datasets, with axis ticks, to an image
+pub fn render_heatmap(
+    histograms: Vec<Histogram2D>,
+    background: BackGround,
+    chosen_color: Vec<Color>,
+    scaling: Scaling,
+) -> RgbImage {
+    check_axes(&histograms);
+    let (kind, axes) = (histograms[0].kind(), histograms[0].axes());
+    let image = draw_heatmap(histograms, background, chosen_color, scaling);
+    info!("Adding axis ticks");
+    add_axis_ticks(image, kind, &axes, background)
+}
+
+/// Plot the first dataset as a heatmap with iso-density lines of the other datasets over it,
+/// or with the lines of the first dataset itself if there is only one, to an image
+pub fn render_contours(
+    histograms: Vec<Histogram2D>,
+    background: BackGround,
+    chosen_color: Vec<Color>,
+    scaling: Scaling,
+    levels: usize,
+) -> RgbImage {
+    check_axes(&histograms);
+    let (kind, axes) = (histograms[0].kind(), histograms[0].axes());
+    // the datasets are normalized together, for the heatmap as well as for the lines
+    let skip = usize::from(histograms.len() > 1);
+    let outlined = scaling.prepare(histograms);
+    let mut image = draw_prepared_heatmap(
+        vec![outlined[0].clone()],
+        background,
+        vec![chosen_color[0]],
+        scaling,
+    );
+    info!("Adding {} contours", levels);
+    for (histogram, color) in outlined.iter().zip(chosen_color).skip(skip) {
+        contour::draw_contours(&mut image, histogram, levels, color_to_rgb(color), scaling);
+    }
+    add_axis_ticks(image, kind, &axes, background)
+}
+
+// the ticks of the variable on the x-axis, and of the accuracy on the y-axis
+fn add_axis_ticks(
+    image: RgbImage,
+    kind: HistogramKind,
+    axes: &Axes,
+    background: BackGround,
+) -> RgbImage {
+    match kind {
+        HistogramKind::LengthAccuracy => axis_ticks::add_ticks(image, axes, background),
+        HistogramKind::Calibration => axis_ticks::add_calibration_ticks(image, axes, background),
+        HistogramKind::TimeAccuracy => axis_ticks::add_time_ticks(image, axes, background),
+    }
+}
+
+/// Plot each dataset in a separate panel, labelled with its name,
+/// with the panels arranged in a grid of (about) equal rows and columns
+pub fn plot_grid(
+    labels: Vec<String>,
+    histograms: Vec<Histogram2D>,
+    background: BackGround,
+    chosen_color: Color,
+    scaling: Scaling,
+    output: &str,
+) {
+    let columns = (histograms.len() as f32).sqrt().ceil() as u32;
+    let rows = (histograms.len() as u32).div_ceil(columns);
+    let mut grid = match background {
+        BackGround::Black => RgbImage::from_pixel(601 * columns, 601 * rows, Rgb([0, 0, 0])),
+        BackGround::White => {
+            RgbImage::from_pixel(601 * columns, 601 * rows, Rgb([255, 255, 255]))
+        }
+    };
+    for (index, (label, histogram)) in labels.into_iter().zip(histograms).enumerate() {
+        let mut panel = render_heatmap(vec![histogram], background, vec![chosen_color], scaling);
+        panel = axis_ticks::add_legend(panel, &[(label, color_to_rgb(chosen_color))]);
+        let (row, column) = (index as u32 / columns, index as u32 % columns);
+        imageops::replace(&mut grid, &panel, (column * 601) as i64, (row * 601) as i64);
+    }
+    info!("Saving image");
+    grid.save(output).expect("Error while saving image");
+}
+
+/// Plot the accuracy predicted from the base qualities (x-axis) against the
+/// accuracy observed from the alignment (y-axis), with the identity diagonal
+/// on which well-calibrated reads are expected
+pub fn plot_calibration(
+    histograms: Vec<Histogram2D>,
+    background: BackGround,
+    chosen_color: Vec<Color>,
+    scaling: Scaling,
+    output: &str,
+) {
+    check_axes(&histograms);
+    let axes = histograms[0].axes();
+    let mut image = draw_heatmap(histograms, background, chosen_color, scaling);
+    info!("Adding identity diagonal and axis ticks");
+    // the diagonal goes from the lowest accuracy in the bottom left
+    // to the highest accuracy in the top right corner
+    draw_line_segment_mut(
+        &mut image,
+        (0.0, transform::PLOT_SIZE as f32),
+        (transform::PLOT_SIZE as f32, 0.0),
+        Rgb([128, 128, 128]),
+    );
+    image = axis_ticks::add_calibration_ticks(image, &axes, background);
+
+    info!("Saving image");
+    image.save(output).expect("Error while saving image");
+}
+
+/// Plot one or up to three overlaid datasets to an image, without axis ticks
+pub fn draw_heatmap(
+    histograms: Vec<Histogram2D>,
+    background: BackGround,
+    chosen_color: Vec<Color>,
+    scaling: Scaling,
+) -> RgbImage {
+    check_axes(&histograms);
+    draw_prepared_heatmap(
+        scaling.prepare(histograms),
+        background,
+        chosen_color,
+        scaling,
+    )
+}
+
+// the heatmap of histograms that are already normalized and smoothed
+fn draw_prepared_heatmap(
+    histograms: Vec<Histogram2D>,
+    background: BackGround,
+    chosen_color: Vec<Color>,
+    scaling: Scaling,
+) -> RgbImage {
+    let mut image = match background {
+        BackGround::Black => RgbImage::from_pixel(601, 601, Rgb([0, 0, 0])),
+        BackGround::White => RgbImage::from_pixel(601, 601, Rgb([255, 255, 255])),
+    };
+
+    if histograms.len() == 1 {
+        // Creating a plot with just a single dataset
+        let histogram = &histograms[0];
+        info!(
+            "Constructing figure with {} colored pixels",
+            histogram.iter().count()
+        );
+        // All counts are scaled to the limits, by default the max value,
+        // after the transformation of the counts
+        let limits = scaling.limits(&histograms);
+        debug!("Max value of histogram: {}", histogram.max());
+        debug!("Limits of the color scale: {:?}", limits);
+        // only do the code below in debug mode
+        if log::log_enabled!(log::Level::Debug) {
+            // debug the length and accuracy of the bin with the highest count
+            if let Some(((length, accuracy), _count)) = histogram
+                .iter()
+                .max_by(|(_bin, a), (_other_bin, b)| a.total_cmp(b))
+            {
+                debug!("Length: {}, Accuracy: {} of max value", length, accuracy);
+            }
+        }
+        // Iterate over the bins with reads and color pixels accordingly
+        for ((length, accuracy), count) in histogram.iter() {
+            let intensity = scaling.intensity(count, limits) as u8;
+            let color = color_at_intensity(chosen_color[0], background, intensity);
+            image.put_pixel(length as u32, accuracy as u32, color);
+        }
+    } else {
+        // Creating a plot of multiple datasets
+        let default = arr1(&[0, 0, 0]);
+        let hashmaps = combine_histograms(&histograms, chosen_color, background, scaling);
+        // Iterate over the bins used by any of the datasets
+        // If that bin is unused in one of the datasets the default (0, 0, 0) is added
+        let bins: BTreeSet<(usize, usize)> =
+            hashmaps.iter().flat_map(|h| h.keys().copied()).collect();
+        for (length, accuracy) in bins {
+            let summed_arr = hashmaps.iter().fold(default.clone(), |sum, h| {
+                sum + h.get(&(length, accuracy)).unwrap_or(&default)
+            });
+            let arr: [u8; 3] = summed_arr.into_raw_vec_and_offset().0.try_into().unwrap();
+            // Use the summed RGB arrays to fill in the pixel
+            image.put_pixel(length as u32, accuracy as u32, Rgb(arr));
+        }
+    }
+    image
+}
+
+#[test]
+fn test_single_file() {
+    let reads = crate::extract_data::bam_to_reads(
+        "test-data/small-test-phased.bam",
+        4,
+        false,
+        crate::extract_data::ReadLength::Query,
+        false,
+        &crate::sampling::Sampling::default(),
+    );
+    let histogram = crate::extract_data::reads_to_histogram(
+        &reads,
+        crate::transform::Axes::new(crate::transform::AccuracyScale::Percent),
+    );
+    plot_heatmap(
+        vec![histogram],
+        BackGround::Black,
+        vec![Color::Purple],
+        Scaling::default(),
+        "accuracy_heatmap_percent_on_black.png",
+    );
+}
+
+#[test]
+fn test_single_file_ubam() {
+    let reads = crate::extract_data::bam_to_reads(
+        "test-data/small-test-phased.bam",
+        4,
+        true,
+        crate::extract_data::ReadLength::Query,
+        false,
+        &crate::sampling::Sampling::default(),
+    );
+    let histogram = crate::extract_data::reads_to_histogram(
+        &reads,
+        crate::transform::Axes::new(crate::transform::AccuracyScale::Percent),
+    );
+    plot_heatmap(
+        vec![histogram],
+        BackGround::Black,
+        vec![Color::Purple],
+        Scaling::default(),
+        "accuracy_heatmap_percent_on_black_ubam.png",
+    );
+}
+
+#[test]
+#[ignore]
+fn test_single_file_from_de() {
+    let reads = crate::extract_data::bam_to_reads(
+        "test-data/small-test-phased_de.bam",
+        4,
+        false,
+        crate::extract_data::ReadLength::Query,
+        false,
+        &crate::sampling::Sampling::default(),
+    );
+    let histogram = crate::extract_data::reads_to_histogram(
+        &reads,
+        crate::transform::Axes::new(crate::transform::AccuracyScale::Percent),
+    );
+    plot_heatmap(
+        vec![histogram],
+        BackGround::Black,
+        vec![Color::Purple],
+        Scaling::default(),
+        "accuracy_heatmap_percent_on_black_from_de.png",
+    );
+}
+
+#[test]
+fn test_single_file_black_phred() {
+    let reads = crate::extract_data::bam_to_reads(
+        "test-data/small-test-phased.bam",
+        4,
+        false,
+        crate::extract_data::ReadLength::Query,
+        false,
+        &crate::sampling::Sampling::default(),
+    );
+    let histogram = crate::extract_data::reads_to_histogram(
+        &reads,
+        crate::transform::Axes::new(crate::transform::AccuracyScale::Phred),
+    );
+    plot_heatmap(
+        vec![histogram],
+        BackGround::Black,
+        vec![Color::Purple],
+        Scaling::default(),
+        "accuracy_heatmap_phred_on_black.png",
+    );
+}
+
+#[test]
+fn test_single_file_phred() {
+    let reads = crate::extract_data::bam_to_reads(
+        "test-data/small-test-phased.bam",
+        4,
+        false,
+        crate::extract_data::ReadLength::Query,
+        false,
+        &crate::sampling::Sampling::default(),
+    );
+    let histogram = crate::extract_data::reads_to_histogram(
+        &reads,
+        crate::transform::Axes::new(crate::transform::AccuracyScale::Phred),
+    );
+    plot_heatmap(
+        vec![histogram],
+        BackGround::White,
+        vec![Color::Red],
+        Scaling::default(),
+        "accuracy_heatmap_phred_on_white.png",
+    );
+}
+
+#[test]
+fn test_single_file_phred_log2() {
+    let reads = crate::extract_data::bam_to_reads(
+        "test-data/small-test-phased.bam",
+        4,
+        false,
+        crate::extract_data::ReadLength::Query,
+        false,
+        &crate::sampling::Sampling::default(),
+    );
+    let histogram = crate::extract_data::reads_to_histogram(
+        &reads,
+        crate::transform::Axes::new(crate::transform::AccuracyScale::Phred),
+    );
+    plot_heatmap(
+        vec![histogram],
+        BackGround::White,
+        vec![Color::Red],
+        Scaling {
+            scale: crate::intensity::CountScale::Log2,
+            pseudocount: 1.0,
+            ..Default::default()
+        },
+        "accuracy_heatmap_phred_log2_on_white.png",
+    );
+}
+
+#[test]
+fn test_vmax_quantile() {
+    let reads = crate::extract_data::bam_to_reads(
+        "test-data/small-test-phased.bam",
+        4,
+        false,
+        crate::extract_data::ReadLength::Query,
+        false,
+        &crate::sampling::Sampling::default(),
+    );
+    let histogram = crate::extract_data::reads_to_histogram(
+        &reads,
+        crate::transform::Axes::new(crate::transform::AccuracyScale::Percent),
+    );
+    // the bins above the quantile are saturated, instead of only the bin with the highest count
+    let saturated = |scaling: Scaling| {
+        let image = render_heatmap(
+            vec![histogram.clone()],
+            BackGround::Black,
+            vec![Color::Red],
+            scaling,
+        );
+        image.pixels().filter(|p| p.0 == [255, 0, 0]).count()
+    };
+    let quantile = Scaling {
+        vmax_quantile: Some(0.9),
+        ..Default::default()
+    };
+    assert!(saturated(quantile) > saturated(Scaling::default()));
+}
+
+#[test]
+fn test_calibration() {
+    let reads = crate::extract_data::bam_to_calibration_reads(
+        "test-data/small-test-phased.bam",
+        4,
+        &crate::sampling::Sampling::default(),
+    );
+    let histogram = crate::extract_data::calibration_to_histogram(
+        &reads,
+        crate::transform::Axes::new(crate::transform::AccuracyScale::Phred),
+    );
+    plot_calibration(
+        vec![histogram],
+        BackGround::Black,
+        vec![Color::Green],
+        Scaling::default(),
+        "accuracy_calibration_phred_on_black.png",
+    );
+}
+
+#[test]
+fn test_split_by_read_group() {
+    let groups = crate::extract_data::bam_to_grouped_reads(
+        "test-data/small-test-phased.bam",
+        4,
+        false,
+        crate::extract_data::ReadLength::Query,
+        false,
+        &crate::extract_data::SplitBy::ReadGroup,
+        &crate::sampling::Sampling::default(),
+    );
+    let (labels, histograms): (Vec<_>, Vec<_>) = groups
+        .iter()
+        .map(|(group, reads)| {
+            (
+                group.clone(),
+                crate::extract_data::reads_to_histogram(
+                    reads,
+                    crate::transform::Axes::new(crate::transform::AccuracyScale::Percent),
+                ),
+            )
+        })
+        .unzip();
+    plot_grid(
+        labels,
+        histograms,
+        BackGround::White,
+        Color::Blue,
+        Scaling::default(),
+        "accuracy_heatmap_grid.png",
+    );
+}
+
+#[test]
+fn test_contours_normalized_together() {
+    let axes = crate::transform::Axes::new(crate::transform::AccuracyScale::Percent);
+    let mut first = Histogram2D::new(crate::histogram::HistogramKind::LengthAccuracy, axes);
+    for x in 0..100 {
+        first.add(100 + x, 100);
+    }
+    let mut second = Histogram2D::new(crate::histogram::HistogramKind::LengthAccuracy, axes);
+    for _ in 0..10 {
+        second.add(300, 500);
+    }
+    let scaling = Scaling {
+        sample_norm: crate::intensity::SampleNorm::Downsample,
+        ..Default::default()
+    };
+    let image = render_contours(
+        vec![first, second],
+        BackGround::Black,
+        vec![Color::Red, Color::Blue],
+        scaling,
+        3,
+    );
+    // the heatmap of the first dataset is downsampled to the reads of the second dataset
+    let filled = (100..200)
+        .filter(|x| image.get_pixel(*x, 100).0[0] > 0)
+        .count();
+    assert_eq!(filled, 10);
+}
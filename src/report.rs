@@ -0,0 +1,250 @@
+// Self-contained HTML report, in which the histograms are drawn by the browser such that
+// the bins can be inspected, with the summary statistics of the datasets
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+use log::info;
+
+use crate::intensity::Scaling;
+use crate::kyb::KybFile;
+use crate::plot::color_to_rgb;
+use crate::stats::Summary;
+use crate::transform::{accuracy_to_phred, AccuracyScale};
+use crate::{BackGround, Color};
+
+const TEMPLATE: &str = include_str!("report.html");
+
+/// Save the histograms and the summaries of the datasets to an HTML file without external assets,
+/// with the datasets in their color and the counts transformed as for the images
+pub fn write_html(
+    path: &str,
+    kyb: &KybFile,
+    summaries: &[Summary],
+    colors: &[Color],
+    background: BackGround,
+    scaling: Scaling,
+) {
+    info!("Saving report to {path}");
+    let file = File::create(path).unwrap_or_else(|e| panic!("Error creating {path}: {e}"));
+    let mut writer = BufWriter::new(file);
+    write_html_file(&mut writer, kyb, summaries, colors, background, scaling)
+        .and_then(|_| writer.flush())
+        .unwrap_or_else(|e| panic!("Error writing {path}: {e}"));
+}
+
+fn write_html_file<W: Write>(
+    writer: &mut W,
+    kyb: &KybFile,
+    summaries: &[Summary],
+    colors: &[Color],
+    background: BackGround,
+    scaling: Scaling,
+) -> io::Result<()> {
+    // the placeholders are filled in a single pass over the template, as the names of the
+    // files and datasets that are filled in could contain a placeholder as well
+    let mut rest = TEMPLATE;
+    for (placeholder, value) in [
+        ("{{METADATA}}", metadata(kyb)),
+        ("{{TABLE}}", table(kyb, summaries)),
+        ("{{DATA}}", data(kyb, colors, background, scaling)),
+    ] {
+        let (before, after) = rest
+            .split_once(placeholder)
+            .expect("Placeholder missing from the report template");
+        writer.write_all(before.as_bytes())?;
+        writer.write_all(value.as_bytes())?;
+        rest = after;
+    }
+    writer.write_all(rest.as_bytes())
+}
+
+fn metadata(kyb: &KybFile) -> String {
+    let metadata = &kyb.metadata;
+    let mut lines = vec![
+        format!("kyber {}", metadata.version),
+        format!("accuracy as {}", metadata.metric),
+        format!("inputs: {}", metadata.inputs.join(", ")),
+    ];
+    if !metadata.filters.is_empty() {
+        lines.push(format!("filters: {}", metadata.filters.join("; ")));
+    }
+    lines
+        .iter()
+        .map(|line| format!("<p class=\"metadata\">{}</p>\n", escape_html(line)))
+        .collect()
+}
+
+// the summary statistics, with the N50 and median accuracy estimated from the bins
+fn table(kyb: &KybFile, summaries: &[Summary]) -> String {
+    let mut table = String::from(
+        "<table>\n<tr><th>dataset</th><th>reads</th><th>bases</th><th>read length N50</th><th>median accuracy</th></tr>\n",
+    );
+    for (summary, (_name, histogram)) in summaries.iter().zip(&kyb.datasets) {
+        let bases = summary
+            .bases
+            .map_or_else(|| String::from("NA"), |b| b.to_string());
+        let n50 = histogram
+            .length_n50()
+            .map_or_else(|| String::from("NA"), |n| format!("{n:.0}"));
+        let median = histogram.median_accuracy().map_or_else(
+            || String::from("NA"),
+            |m| match histogram.scale() {
+                AccuracyScale::Percent => format!("{m:.2}%"),
+                AccuracyScale::Phred => format!("Q{:.2}", accuracy_to_phred(m)),
+            },
+        );
+        let _ = writeln!(
+            table,
+            "<tr><td>{}</td><td>{}</td><td>{bases}</td><td>{n50}</td><td>{median}</td></tr>",
+            escape_html(&summary.name),
+            summary.reads
+        );
+    }
+    table.push_str("</table>");
+    table
+}
+
+// the bins of each dataset as a flat list of x, y, count and intensity, with the count of
+// reads in the bin and the intensity as in the images, e.g. after smoothing
+fn data(kyb: &KybFile, colors: &[Color], background: BackGround, scaling: Scaling) -> String {
+    let histograms = kyb
+        .datasets
+        .iter()
+        .map(|(_name, h)| h.clone())
+        .collect::<Vec<_>>();
+    let prepared = scaling.prepare(histograms);
+    let limits = scaling.limits(&prepared);
+    let mut datasets = vec![];
+    for (((name, histogram), prepared), color) in kyb.datasets.iter().zip(&prepared).zip(colors) {
+        let mut bins = String::new();
+        for ((x, y), count) in prepared.iter() {
+            let intensity = scaling.intensity(count, limits).round();
+            let _ = write!(bins, "{x},{y},{},{intensity},", histogram.get(x, y));
+        }
+        // bins with reads can be empty after smoothing, but are still shown in the tooltip
+        if scaling.smooth.is_some() {
+            for ((x, y), count) in histogram
+                .iter()
+                .filter(|((x, y), _)| prepared.get(*x, *y) == 0.0)
+            {
+                let _ = write!(bins, "{x},{y},{count},0,");
+            }
+        }
+        let rgb = color_to_rgb(*color).0;
+        datasets.push(format!(
+            "{{\"name\":{},\"color\":[{},{},{}],\"bins\":[{}]}}",
+            json_string(name),
+            rgb[0],
+            rgb[1],
+            rgb[2],
+            bins.trim_end_matches(',')
+        ));
+    }
+    let first = &kyb.datasets[0].1;
+    let axes = first.axes();
+    let edges = |edges: &[f32]| {
+        edges
+            .iter()
+            .map(|e| e.to_string())
+            .collect::<Vec<_>>()
+            .join(",")
+    };
+    format!(
+        "{{\"background\":\"{}\",\"axes\":{{\"scale\":\"{}\",\"max_length\":{},\"min_accuracy\":{},\"max_accuracy\":{}}},\"x_edges\":[{}],\"y_edges\":[{}],\"datasets\":[{}]}}",
+        match background {
+            BackGround::Black => "black",
+            BackGround::White => "white",
+        },
+        match axes.scale {
+            AccuracyScale::Percent => "percent",
+            AccuracyScale::Phred => "phred",
+        },
+        axes.max_length,
+        axes.min_accuracy,
+        axes.max_accuracy,
+        edges(first.x_edges()),
+        edges(first.y_edges()),
+        datasets.join(",")
+    )
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+// a string in JSON, which also cannot end the script in which it is embedded
+fn json_string(text: &str) -> String {
+    let mut json = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '<' => json.push_str("\\u003c"),
+            c if c.is_control() => {
+                let _ = write!(json, "\\u{:04x}", c as u32);
+            }
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+#[test]
+fn test_json_string() {
+    assert_eq!(json_string("sample"), "\"sample\"");
+    assert_eq!(
+        json_string("a \"b\"\\</script>\n"),
+        "\"a \\\"b\\\"\\\\\\u003c/script>\\u000a\""
+    );
+}
+
+#[test]
+fn test_write_html() {
+    let axes = crate::transform::Axes::new(AccuracyScale::Percent);
+    let kind = crate::histogram::HistogramKind::LengthAccuracy;
+    let mut histogram = crate::histogram::Histogram2D::new(kind, axes);
+    histogram.add(400, 100);
+    histogram.add(400, 100);
+    histogram.add(200, 300);
+    let kyb = KybFile {
+        metadata: crate::kyb::Metadata::new(
+            crate::kyb::Metric::GapCompressedIdentity,
+            vec![],
+            vec![
+                String::from("<run>.bam"),
+                String::from("{{TABLE}}{{DATA}}.bam"),
+            ],
+        ),
+        datasets: vec![(String::from("<run>"), histogram)],
+    };
+    let summaries = [Summary {
+        name: String::from("<run>"),
+        reads: 3,
+        bases: None,
+    }];
+    let mut buffer = vec![];
+    write_html_file(
+        &mut buffer,
+        &kyb,
+        &summaries,
+        &[Color::Red],
+        BackGround::Black,
+        Scaling::default(),
+    )
+    .unwrap();
+    let html = String::from_utf8(buffer).unwrap();
+    // the placeholders in the names of the inputs are not filled in
+    assert!(html.contains("inputs: &lt;run&gt;.bam, {{TABLE}}{{DATA}}.bam"));
+    assert_eq!(html.matches("<table>").count(), 1);
+    assert!(html.contains("<td>&lt;run&gt;</td><td>3</td><td>NA</td>"));
+    assert!(html.contains(
+        "\"datasets\":[{\"name\":\"\\u003crun>\",\"color\":[255,0,0],\"bins\":[400,100,2,255,200,300,1,128]}]"
+    ));
+    // the report has no external assets
+    assert!(!html.contains("src=") && !html.contains("href="));
+}
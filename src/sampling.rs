@@ -0,0 +1,89 @@
+// Subsampling of reads for fast previews, which is deterministic
+// by hashing the read name in the same way as samtools view -s
+
+/// Which reads are used: a fraction of the reads selected by the hash of their name
+/// and/or at most a maximal number of reads
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Sampling {
+    pub fraction: Option<f64>,
+    pub seed: u32,
+    /// the number of records read, of which some reads can be skipped afterwards
+    pub max_reads: Option<usize>,
+}
+
+impl Sampling {
+    /// Whether a read is selected by --fraction, based on the hash of its name
+    /// all alignments of a read are therefore either selected or not
+    pub fn selects(&self, qname: &[u8]) -> bool {
+        match self.fraction {
+            None => true,
+            Some(fraction) => {
+                let hash = wang_hash(x31_hash(qname) ^ self.seed);
+                f64::from(hash & 0xffffff) / f64::from(0x1000000) < fraction
+            }
+        }
+    }
+
+    /// Whether any subsampling is applied
+    pub fn is_active(&self) -> bool {
+        self.fraction.is_some() || self.max_reads.is_some()
+    }
+}
+
+// the string hash of htslib's khash, as used by samtools view -s
+fn x31_hash(name: &[u8]) -> u32 {
+    name.iter()
+        .fold(0u32, |h, c| (h << 5).wrapping_sub(h).wrapping_add(u32::from(*c)))
+}
+
+// integer hash of htslib's khash, to spread the x31 hashes uniformly
+fn wang_hash(mut key: u32) -> u32 {
+    key = key.wrapping_add(!(key << 15));
+    key ^= key >> 10;
+    key = key.wrapping_add(key << 3);
+    key ^= key >> 6;
+    key = key.wrapping_add(!(key << 11));
+    key ^= key >> 16;
+    key
+}
+
+/// Parse the --fraction argument, which has to be between 0 and 1
+pub fn parse_fraction(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(fraction) if fraction > 0.0 && fraction <= 1.0 => Ok(fraction),
+        _ => Err(format!("Invalid fraction {value}, expected a number in (0, 1]")),
+    }
+}
+
+#[test]
+fn test_selects_is_deterministic() {
+    let sampling = Sampling {
+        fraction: Some(0.5),
+        ..Default::default()
+    };
+    for name in [&b"read1"[..], b"read2", b"read3"] {
+        assert_eq!(sampling.selects(name), sampling.selects(name));
+    }
+}
+
+#[test]
+fn test_selects_fraction() {
+    let sampling = Sampling {
+        fraction: Some(0.1),
+        seed: 7,
+        ..Default::default()
+    };
+    let selected = (0..10000)
+        .filter(|i| sampling.selects(format!("read{i}").as_bytes()))
+        .count();
+    assert!((800..1200).contains(&selected));
+    assert!(Sampling::default().selects(b"read1"));
+}
+
+#[test]
+fn test_parse_fraction() {
+    assert_eq!(parse_fraction("0.05"), Ok(0.05));
+    assert!(parse_fraction("0").is_err());
+    assert!(parse_fraction("1.5").is_err());
+    assert!(parse_fraction("half").is_err());
+}
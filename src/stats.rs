@@ -0,0 +1,40 @@
+use crate::extract_data::ReadAccuracy;
+use crate::sampling::Sampling;
+
+/// Summary of the reads of a dataset, which is an input file or a group of reads
+pub struct Summary {
+    pub name: String,
+    pub reads: usize,
+    /// not available for the calibration plot
+    pub bases: Option<u64>,
+}
+
+impl Summary {
+    pub fn from_reads(name: &str, reads: &[ReadAccuracy]) -> Summary {
+        Summary {
+            name: name.to_string(),
+            reads: reads.len(),
+            bases: Some(reads.iter().map(|r| u64::from(r.length)).sum()),
+        }
+    }
+}
+
+/// Print the summaries as a table to stdout, with the subsampling
+/// that was applied so that counts can be scaled back
+pub fn print_stats(summaries: &[Summary], sampling: &Sampling) {
+    println!("name\treads\tbases\tfraction\tmax_reads");
+    for summary in summaries {
+        println!(
+            "{}\t{}\t{}\t{}\t{}",
+            summary.name,
+            summary.reads,
+            summary
+                .bases
+                .map_or_else(|| String::from("NA"), |b| b.to_string()),
+            sampling.fraction.unwrap_or(1.0),
+            sampling
+                .max_reads
+                .map_or_else(|| String::from("NA"), |m| m.to_string()),
+        );
+    }
+}
@@ -1,51 +1,262 @@
 // The transformations below and the minimal and maximal cutoffs
 // below make sure that both lengths and accuracies end up in an equal space
 // the current parameters result in a 600*600 image
+// these are the default axes, other ranges are possible with Axes
 
-use std::cmp::{max, min};
+use std::cmp::min;
 
-const RESOLUTION_FACTOR: f32 = 10.0;
-const _MIN_LENGTH: usize = 10;
 pub(crate) const MAX_LENGTH: usize = 1000000;
 pub(crate) const MIN_IDENTITY: f32 = 70.0;
 const MAX_PHRED: f32 = 40.0;
+// the length in hours of the longest ONT runs
+pub(crate) const MAX_TIME: f32 = 72.0;
+pub(crate) const PLOT_SIZE: usize = 600;
 
-// log10-transform the read lengths, which are limited to 1M reads
-// log10(1M) = 6, multiply by 50 to get a 600 pixels axis
+// log10-transform the read lengths on the default axis, which is limited to 1M reads
 pub fn transform_length(seqlen: usize) -> usize {
-    min(
-        ((MAX_LENGTH as f32).log10() * 10.0 * RESOLUTION_FACTOR) as usize,
-        ((seqlen as f32).log10() * 10.0 * RESOLUTION_FACTOR) as usize,
-    )
+    Axes::new(AccuracyScale::Percent).transform_length(seqlen)
 }
 
-// identities are converted to error rate to start the plot from the top left corner
-// minimal accuracy is 70, so the 30 accuracies levels (after cast to usize)
-// are multiplied by 20 to get a 600 pixels axis
+// identities are converted to error rate to start the plot from the top left corner,
+// on the default axis from 70 to 100%
 pub fn transform_accuracy_percent(identity: f32) -> usize {
-    min(
-        (RESOLUTION_FACTOR * 2.0 * (100.0 - MIN_IDENTITY)) as usize,
-        (RESOLUTION_FACTOR * 2.0 * (100.0 - identity)) as usize,
-    )
+    Axes::new(AccuracyScale::Percent).transform_accuracy(identity)
 }
 
-// identities are converted to phred scale and capped at 40
-// this leaves us with 40 accuracy levels (after cast to usize)
-// and this is multiplied by 7.5 to get a 600 pixels axis
+// identities are converted to phred scale, on the default axis from Q0 to Q40
 pub fn transform_accuracy_phred(identity: f32) -> usize {
-    max(
-        0,
-        (15.0 * (MAX_PHRED - accuracy_to_phred(identity))) as usize,
-    )
+    Axes::new(AccuracyScale::Phred).transform_accuracy(identity)
 }
 
-fn accuracy_to_phred(identity: f32) -> f32 {
+pub(crate) fn accuracy_to_phred(identity: f32) -> f32 {
     -10.0 * (1.0 - identity / 100.0).log10()
 }
 
+pub(crate) fn phred_to_accuracy(phred: f32) -> f32 {
+    100.0 * (1.0 - 10.0f32.powf(-phred / 10.0))
+}
+
+/// Scale of the accuracy axis, in percent identity or on a phred scale
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AccuracyScale {
+    Percent,
+    Phred,
+}
+
+impl AccuracyScale {
+    pub fn from_phred(phred: bool) -> AccuracyScale {
+        if phred {
+            AccuracyScale::Phred
+        } else {
+            AccuracyScale::Percent
+        }
+    }
+
+    pub fn is_phred(self) -> bool {
+        self == AccuracyScale::Phred
+    }
+}
+
+/// The ranges of the axes, which determine how lengths and accuracies are transformed
+/// to the pixels of the plot, as in the functions above for the default ranges
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Axes {
+    pub scale: AccuracyScale,
+    /// longest read length, at the right of the x-axis
+    pub max_length: usize,
+    /// lowest accuracy at the bottom of the y-axis, in percent or phred
+    pub min_accuracy: f32,
+    /// highest accuracy at the top of the y-axis, in percent or phred
+    pub max_accuracy: f32,
+    /// latest time into the run in hours, at the right of the x-axis of the time plot
+    pub max_time: f32,
+}
+
+impl Axes {
+    /// Lengths up to 1M, times up to 72 hours and accuracies from 70% to 100% or from Q0 to Q40
+    pub fn new(scale: AccuracyScale) -> Axes {
+        let (min_accuracy, max_accuracy) = match scale {
+            AccuracyScale::Percent => (MIN_IDENTITY, 100.0),
+            AccuracyScale::Phred => (0.0, MAX_PHRED),
+        };
+        Axes {
+            scale,
+            max_length: MAX_LENGTH,
+            min_accuracy,
+            max_accuracy,
+            max_time: MAX_TIME,
+        }
+    }
+
+    /// Change the ranges of the axes, keeping the default for the ranges that are None
+    pub fn with_ranges(
+        self,
+        max_length: Option<usize>,
+        min_accuracy: Option<f32>,
+        max_accuracy: Option<f32>,
+    ) -> Result<Axes, String> {
+        let axes = Axes {
+            max_length: max_length.unwrap_or(self.max_length),
+            min_accuracy: min_accuracy.unwrap_or(self.min_accuracy),
+            max_accuracy: max_accuracy.unwrap_or(self.max_accuracy),
+            ..self
+        };
+        let highest = match axes.scale {
+            AccuracyScale::Percent => 100.0,
+            AccuracyScale::Phred => f32::INFINITY,
+        };
+        if axes.max_length < 10 {
+            Err(format!("The maximal length {} should be at least 10", axes.max_length))
+        } else if !(0.0 <= axes.min_accuracy
+            && axes.min_accuracy < axes.max_accuracy
+            && axes.max_accuracy <= highest)
+        {
+            Err(format!(
+                "The accuracy range from {} to {} is invalid for the {:?} scale",
+                axes.min_accuracy, axes.max_accuracy, axes.scale
+            ))
+        } else {
+            Ok(axes)
+        }
+    }
+
+    /// Change the latest time into the run on the x-axis of the time plot, if not None
+    pub fn with_max_time(self, max_time: Option<f32>) -> Result<Axes, String> {
+        match max_time {
+            Some(hours) if !(hours > 0.0 && hours.is_finite()) => Err(format!(
+                "The maximal time of {hours} hours should be positive"
+            )),
+            Some(hours) => Ok(Axes {
+                max_time: hours,
+                ..self
+            }),
+            None => Ok(self),
+        }
+    }
+
+    // log10-transform the read lengths, with the longest length at the end of the axis
+    pub fn transform_length(&self, seqlen: usize) -> usize {
+        let factor = PLOT_SIZE as f32 / (self.max_length as f32).log10();
+        min(PLOT_SIZE, ((seqlen as f32).log10() * factor) as usize)
+    }
+
+    /// The position on the y-axis of an accuracy in percent or phred, counted from the top
+    pub fn accuracy_position(&self, value: f32) -> f32 {
+        let factor = PLOT_SIZE as f32 / (self.max_accuracy - self.min_accuracy);
+        factor * (self.max_accuracy - value)
+    }
+
+    // accuracies above the range end up at the top, and below the range at the bottom
+    pub fn transform_accuracy(&self, identity: f32) -> usize {
+        let value = match self.scale {
+            AccuracyScale::Percent => identity,
+            AccuracyScale::Phred => accuracy_to_phred(identity),
+        };
+        min(PLOT_SIZE, self.accuracy_position(value) as usize)
+    }
+
+    // the time into the run in hours is linear, with later reads at the end of the axis
+    pub fn transform_time(&self, hours: f32) -> usize {
+        min(PLOT_SIZE, (hours.max(0.0) * PLOT_SIZE as f32 / self.max_time) as usize)
+    }
+
+    // the calibration plot has the predicted accuracy on the x-axis
+    // which is mirrored compared to the y-axis to have the lowest accuracy on the left
+    pub fn transform_predicted_accuracy(&self, identity: f32) -> usize {
+        PLOT_SIZE - self.transform_accuracy(identity)
+    }
+
+    // the inverse of the transformations above, giving the value at the edge of a bin
+    // lengths increase with the bin, while accuracies (in percent) decrease
+    pub fn length_bin_edge(&self, bin: f32) -> f32 {
+        10.0f32.powf(bin * (self.max_length as f32).log10() / PLOT_SIZE as f32)
+    }
+
+    pub fn time_bin_edge(&self, bin: f32) -> f32 {
+        bin * self.max_time / PLOT_SIZE as f32
+    }
+
+    pub fn accuracy_bin_edge(&self, bin: f32) -> f32 {
+        let factor = PLOT_SIZE as f32 / (self.max_accuracy - self.min_accuracy);
+        let value = self.max_accuracy - bin / factor;
+        match self.scale {
+            AccuracyScale::Percent => value,
+            AccuracyScale::Phred => phred_to_accuracy(value),
+        }
+    }
+}
+
 #[test]
 fn test_accuracy_to_phred() {
     assert!((accuracy_to_phred(90.0) - 10.0).abs() < 0.01);
     assert!((accuracy_to_phred(99.0) - 20.0).abs() < 0.01);
     assert!((accuracy_to_phred(99.9) - 30.0).abs() < 0.01);
 }
+
+#[test]
+fn test_default_axes() {
+    // the positions on the default axes are unchanged since these could be configured
+    let identities = [0.0, 50.0, 69.9, 70.0, 85.5, 99.0, 99.99, 100.0];
+    let percent = [600, 600, 600, 600, 290, 20, 0, 0];
+    let phred = [600, 554, 521, 521, 474, 299, 0, 0];
+    for ((identity, percent), phred) in identities.into_iter().zip(percent).zip(phred) {
+        assert_eq!(transform_accuracy_percent(identity), percent);
+        assert_eq!(transform_accuracy_phred(identity), phred);
+    }
+    let lengths = [1, 10, 1000, 123456, 1000000, 5000000];
+    let positions = [0, 100, 300, 509, 600, 600];
+    for (length, position) in lengths.into_iter().zip(positions) {
+        assert_eq!(transform_length(length), position);
+    }
+}
+
+#[test]
+fn test_axes_with_ranges() {
+    let hifi = Axes::new(AccuracyScale::Phred)
+        .with_ranges(None, Some(20.0), Some(50.0))
+        .unwrap();
+    assert_eq!(hifi.transform_accuracy(99.999), 0);
+    assert_eq!(hifi.transform_accuracy(99.95), 339);
+    assert_eq!(hifi.transform_accuracy(90.0), 600);
+    let ultra_long = Axes::new(AccuracyScale::Percent)
+        .with_ranges(Some(4000000), None, None)
+        .unwrap();
+    assert_eq!(ultra_long.transform_length(4000000), 600);
+    assert!(ultra_long.transform_length(1000000) < 600);
+    assert!(Axes::new(AccuracyScale::Percent)
+        .with_ranges(None, Some(90.0), Some(110.0))
+        .is_err());
+    assert!(Axes::new(AccuracyScale::Phred)
+        .with_ranges(None, Some(30.0), Some(20.0))
+        .is_err());
+}
+
+#[test]
+fn test_bin_edges() {
+    let axes = Axes::new(AccuracyScale::Percent);
+    assert_eq!(axes.transform_length(axes.length_bin_edge(300.0).round() as usize), 300);
+    assert_eq!(axes.transform_accuracy(axes.accuracy_bin_edge(200.0)), 200);
+    let axes = Axes::new(AccuracyScale::Phred);
+    assert!((axes.accuracy_bin_edge(300.0) - 99.0).abs() < 0.01);
+    assert_eq!(axes.time_bin_edge(300.0), 36.0);
+}
+
+#[test]
+fn test_transform_time() {
+    let axes = Axes::new(AccuracyScale::Percent);
+    assert_eq!(axes.transform_time(0.0), 0);
+    assert_eq!(axes.transform_time(36.0), 300);
+    // reads after the end of the axis end up at the right
+    assert_eq!(axes.transform_time(100.0), 600);
+    let short = axes.with_max_time(Some(24.0)).unwrap();
+    assert_eq!(short.transform_time(12.0), 300);
+    assert!(axes.with_max_time(Some(0.0)).is_err());
+}
+
+#[test]
+fn test_transform_predicted_accuracy() {
+    let percent = Axes::new(AccuracyScale::Percent);
+    assert_eq!(percent.transform_predicted_accuracy(70.0), 0);
+    assert_eq!(percent.transform_predicted_accuracy(90.0), 400);
+    assert_eq!(Axes::new(AccuracyScale::Phred).transform_predicted_accuracy(100.0), 600);
+}
@@ -1,5 +1,9 @@
+use clap::ValueEnum;
 use std::path::PathBuf;
 
+use crate::extract_data::SplitBy;
+use crate::intensity::CountScale;
+
 pub fn is_file(pathname: &str) -> Result<(), String> {
     if pathname == "-" {
         return Ok(());
@@ -11,3 +15,95 @@ pub fn is_file(pathname: &str) -> Result<(), String> {
         Err(format!("Input file {} is invalid", path.display()))
     }
 }
+
+/// Parse the --split-by argument: RG, SM, BC or tag:XX for any other two-letter tag
+pub fn parse_split_by(value: &str) -> Result<SplitBy, String> {
+    match value {
+        "RG" => Ok(SplitBy::ReadGroup),
+        "SM" => Ok(SplitBy::Sample),
+        "BC" => Ok(SplitBy::Barcode),
+        _ => match value.strip_prefix("tag:").map(str::as_bytes) {
+            Some(&[a, b]) => Ok(SplitBy::Tag([a, b])),
+            _ => Err(format!(
+                "Invalid value {value} to split by, expected RG, SM, BC or tag:XX"
+            )),
+        },
+    }
+}
+
+/// An output image, with optional settings that override --phred and --scale for this image
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OutputSpec {
+    pub path: String,
+    pub phred: Option<bool>,
+    pub scale: Option<CountScale>,
+}
+
+/// Parse the --output argument: a file name, optionally followed by a colon and a comma-separated
+/// list of phred|percent and the scale of the counts, e.g. heatmap.png:phred,log10
+/// with normalize as the scale log2
+pub fn parse_output(value: &str) -> Result<OutputSpec, String> {
+    let mut spec = OutputSpec {
+        path: value.to_string(),
+        phred: None,
+        scale: None,
+    };
+    if let Some((path, settings)) = value.rsplit_once(':') {
+        let mut parsed = spec.clone();
+        parsed.path = path.to_string();
+        let mut unknown = vec![];
+        for setting in settings.split(',') {
+            match setting {
+                "phred" => parsed.phred = Some(true),
+                "percent" => parsed.phred = Some(false),
+                "normalize" => parsed.scale = Some(CountScale::Log2),
+                _ => match CountScale::from_str(setting, false) {
+                    Ok(scale) => parsed.scale = Some(scale),
+                    Err(_) => unknown.push(setting),
+                },
+            }
+        }
+        match unknown.first() {
+            None => spec = parsed,
+            // a colon followed by a file name rather than by settings is part of the file name
+            Some(_) if unknown.len() == settings.split(',').count() && settings.contains('.') => {}
+            Some(setting) => {
+                return Err(format!(
+                    "Invalid output {value}, unknown setting {setting}, expected phred, percent or a scale of the counts"
+                ))
+            }
+        }
+    }
+    if spec.path.is_empty() {
+        return Err(format!("Invalid output {value}, the file name is empty"));
+    }
+    Ok(spec)
+}
+
+#[test]
+fn test_parse_split_by() {
+    assert_eq!(parse_split_by("RG"), Ok(SplitBy::ReadGroup));
+    assert_eq!(parse_split_by("SM"), Ok(SplitBy::Sample));
+    assert_eq!(parse_split_by("tag:HP"), Ok(SplitBy::Tag(*b"HP")));
+    assert!(parse_split_by("tag:HPX").is_err());
+    assert!(parse_split_by("XX").is_err());
+}
+
+#[test]
+fn test_parse_output() {
+    let spec = parse_output("heatmap.png").unwrap();
+    assert_eq!(spec.path, "heatmap.png");
+    assert_eq!((spec.phred, spec.scale), (None, None));
+    let spec = parse_output("heatmap.png:phred,normalize").unwrap();
+    assert_eq!(spec.path, "heatmap.png");
+    assert_eq!((spec.phred, spec.scale), (Some(true), Some(CountScale::Log2)));
+    let spec = parse_output("heatmap.png:asinh").unwrap();
+    assert_eq!((spec.phred, spec.scale), (None, Some(CountScale::Asinh)));
+    let spec = parse_output("run:2.png").unwrap();
+    assert_eq!(spec.path, "run:2.png");
+    assert!(parse_output(":phred").is_err());
+    // a misspelled setting is not taken as part of the file name
+    let error = parse_output("heatmap.png:phred,lgo10").unwrap_err();
+    assert!(error.contains("unknown setting lgo10"));
+    assert!(parse_output("heatmap.png:lgo10").is_err());
+}